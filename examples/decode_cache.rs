@@ -0,0 +1,53 @@
+// Times the interpreter with and without the decoded instruction cache on a
+// tight nested loop, alternating the two so both see the same machine load.
+//
+// Usage: cargo run --release --example decode_cache [rounds]
+
+extern crate aoc_2019;
+
+use std::time::{Duration, Instant};
+use aoc_2019::prelude::*;
+
+// 3000 * 1000 iterations of a five instruction body, 18M instructions in all
+const PROG: &str = "
+        RBX #100
+outer:  ADD #0, #0, [j]
+inner:  MUL [i], [j], [t]
+        ADD [t], [acc], [acc]
+        ADD rb+0, #1, rb+0
+        ADD [j], #1, [j]
+        LT  [j], #1000, [c]
+        JNZ [c], #inner
+        ADD [i], #1, [i]
+        LT  [i], #3000, [c]
+        JNZ [c], #outer
+        OUT [acc]
+        HLT
+i:      data 0
+j:      data 0
+t:      data 0
+c:      data 0
+acc:    data 0
+";
+
+fn main() {
+    let rounds: usize = std::env::args().nth(1).map_or(15, |arg| arg.parse().expect("rounds must be a number"));
+    let prog = assemble(PROG).unwrap();
+    let mut times: [Vec<Duration>; 2] = [Vec::new(), Vec::new()];
+
+    for _ in 0..rounds {
+        for (idx, cached) in [true, false].into_iter().enumerate() {
+            let mut vm = Machine::new(&prog);
+            vm.cache_decoding(cached);
+            let start = Instant::now();
+            vm.run().unwrap();
+            times[idx].push(start.elapsed());
+            assert_eq!(vm.dump_output().back(), Some(&2247000750000));
+        }
+    }
+
+    for (name, times) in ["cached", "uncached"].iter().zip(times.iter_mut()) {
+        times.sort();
+        println!("{name:<9} min {:?}  median {:?}", times[0], times[times.len() / 2]);
+    }
+}
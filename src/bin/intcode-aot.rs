@@ -0,0 +1,27 @@
+// Translates an Intcode program into Rust source
+//
+// Usage: intcode-aot <program file> [function name]
+
+extern crate aoc_2019;
+
+use aoc_2019::prelude::*;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(path) = args.get(1) else {
+        eprintln!("usage: intcode-aot <program file> [function name]");
+        std::process::exit(2);
+    };
+    let name = args.get(2).map_or("translated", |name| name.as_str());
+
+    let text = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("cannot read {path}: {err}");
+        std::process::exit(1);
+    });
+    let prog = Machine::parse(text.trim()).unwrap_or_else(|err| {
+        eprintln!("cannot parse {path}: {err}");
+        std::process::exit(1);
+    });
+
+    print!("{}", translate(&prog, name));
+}
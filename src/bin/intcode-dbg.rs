@@ -0,0 +1,285 @@
+// Interactive Intcode debugger
+//
+// Usage: intcode-dbg <program file> [input ...]
+
+extern crate aoc_2019;
+
+use aoc_2019::prelude::*;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+  s, step [n]            execute n instructions (default 1)
+  c, continue            run until halt, input wait, breakpoint or fault
+  b, break [addr]        set a breakpoint, or list breakpoints and watchpoints
+  w, watch addr [r|w|rw] set a watchpoint on a memory cell (default rw)
+  d, delete id           remove a breakpoint or watchpoint
+  x, mem addr [n]        print n memory cells (default 8)
+  set addr value         write a memory cell
+  i, in value...         push inputs, a quoted string pushes its characters
+  o, out                 show the output queue
+  r, regs                show ip, relative base, state and pending input
+  l, dis [addr] [n]      disassemble n instructions around ip or from addr
+  g, graph               print the control-flow graph of the program as Graphviz DOT
+  decompile              print the program as C-like pseudo-code
+  p, profile [on|off|n]  start or stop profiling, or report the n hottest spots (default 10)
+  reset                  reload the program, keeping breakpoints and watchpoints
+  h, help                show this help
+  q, quit                leave the debugger
+an empty line repeats the last command";
+
+struct Debugger {
+    prog: Program,
+    vm: Machine,
+}
+
+impl Debugger {
+    fn new(prog: Program) -> Self {
+        let vm = Machine::new(&prog);
+        Self { prog, vm }
+    }
+
+    // Executes a command line, returns false to quit
+    fn exec(&mut self, line: &str) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some(cmd) = args.first() else {
+            return Ok(true);
+        };
+
+        match *cmd {
+            "s" | "step" => {
+                let count = arg_or(&args, 1, 1)?;
+                for _ in 0..count {
+                    match self.vm.step() {
+                        Ok(Some(step)) => println!("{}", describe(&step)),
+                        Ok(None) => {
+                            println!("{}", self.vm.state());
+                            break;
+                        }
+                        Err(err) => return Err(err.to_string()),
+                    }
+                    if let MachineState::Break(trap) = self.vm.state() {
+                        println!("stopped at {trap}");
+                        break;
+                    }
+                }
+            }
+            "c" | "continue" => {
+                let before = self.vm.dump_output().len();
+                let state = self.vm.resume().map_err(|err| err.to_string())?;
+                let new: Vec<Word> = self.vm.dump_output().iter().skip(before).copied().collect();
+                if !new.is_empty() {
+                    println!("output: {new:?}");
+                }
+                println!("{state}");
+                self.show_at(self.vm.ip(), 1);
+            }
+            "b" | "break" => match args.get(1) {
+                Some(addr) => {
+                    let addr = num(addr)?;
+                    println!("breakpoint {} at {addr:04}", self.vm.break_at(addr));
+                }
+                None => {
+                    for (id, addr) in self.vm.breakpoints() {
+                        println!("{id:>3}  break  {addr:04}");
+                    }
+                    for (id, addr, kind) in self.vm.watchpoints() {
+                        println!("{id:>3}  watch  {addr:04} {kind:?}");
+                    }
+                }
+            },
+            "w" | "watch" => {
+                let addr = num(args.get(1).ok_or("watch needs an address")?)?;
+                let kind = match args.get(2).copied().unwrap_or("rw") {
+                    "r" => WatchKind::Read,
+                    "w" => WatchKind::Write,
+                    "rw" => WatchKind::Any,
+                    other => return Err(format!("unknown watch kind '{other}'")),
+                };
+                println!("watchpoint {} on [{addr}]", self.vm.watch(addr, kind));
+            }
+            "d" | "delete" => {
+                let id = num(args.get(1).ok_or("delete needs an id")?)?;
+                if !self.vm.unset(id) {
+                    return Err(format!("no breakpoint or watchpoint {id}"));
+                }
+            }
+            "x" | "mem" => {
+                let addr: usize = num(args.get(1).ok_or("mem needs an address")?)?;
+                let count = arg_or(&args, 2, 8)?;
+                let end = addr.saturating_add(count);
+                for row in (addr..end).step_by(8) {
+                    let cells: Vec<String> = (row..row.saturating_add(8).min(end))
+                        .map(|idx| self.vm.read(idx).to_string())
+                        .collect();
+                    println!("{row:04}  {}", cells.join(" "));
+                }
+            }
+            "set" => {
+                let addr = num(args.get(1).ok_or("set needs an address")?)?;
+                let value = num(args.get(2).ok_or("set needs a value")?)?;
+                if !self.vm.memory().in_bounds(addr) {
+                    return Err(format!("address {addr} is past the memory limit"));
+                }
+                self.vm.inject(addr, value);
+            }
+            "i" | "in" => {
+                let rest = line.trim_start()[cmd.len()..].trim();
+                let inputs: Vec<Word> = match rest.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                    Some(text) => text.replace("\\n", "\n").chars().map(|ch| ch as Word).collect(),
+                    None => args[1..].iter().map(|arg| num(arg)).collect::<Result<_, _>>()?,
+                };
+                self.vm.input_ext(&inputs);
+            }
+            "o" | "out" => println!("{:?}", self.vm.dump_output()),
+            "r" | "regs" => {
+                println!("ip    {}", self.vm.ip());
+                println!("rb    {}", self.vm.rb());
+                println!("state {}", self.vm.state());
+                println!("input {:?}", self.vm.input_queue());
+                println!("steps {}", self.vm.counters().instructions());
+            }
+            "l" | "dis" => {
+                let count = arg_or(&args, 2, 10)?;
+                match args.get(1) {
+                    Some(addr) => self.show_from(num(addr)?, count),
+                    None => self.show_at(self.vm.ip(), count),
+                }
+            }
+            "g" | "graph" => print!("{}", control_flow(&self.prog).to_dot()),
+            "decompile" => print!("{}", decompile(&self.prog)),
+            "p" | "profile" => match args.get(1).copied() {
+                Some("on") => self.vm.start_profile(),
+                Some("off") => {
+                    self.vm.stop_profile();
+                }
+                _ => {
+                    let profile = self.vm.profile().ok_or("not profiling, use 'profile on'")?;
+                    let top = arg_or(&args, 1, 10)?;
+                    print!("{}", profile.report(&self.vm.memory().prefix(), top));
+                }
+            },
+            "reset" => self.vm.reboot(&self.prog),
+            "h" | "help" => println!("{HELP}"),
+            "q" | "quit" => return Ok(false),
+            other => return Err(format!("unknown command '{other}', try help")),
+        }
+
+        Ok(true)
+    }
+
+    // Lists instructions around an address, using the static disassembly to find boundaries
+    fn show_at(&self, addr: usize, count: usize) {
+        let dis = disassemble(&self.vm.memory().prefix());
+        let insts: Vec<&Inst> = dis.instructions().collect();
+        match insts.iter().position(|inst| inst.addr == addr) {
+            Some(pos) => {
+                let start = pos.saturating_sub(count / 3);
+                for inst in insts.iter().skip(start).take(count) {
+                    self.print_inst(inst);
+                }
+            }
+            None => self.show_from(addr, count),
+        }
+    }
+
+    // Lists instructions decoded linearly from an address
+    fn show_from(&self, mut addr: usize, count: usize) {
+        let mem = self.vm.memory().prefix();
+        for _ in 0..count {
+            match Inst::decode(&mem, addr) {
+                Some(inst) => {
+                    self.print_inst(&inst);
+                    addr += inst.size();
+                }
+                None if addr < mem.len() => {
+                    println!("   {addr:04}  {:<24} DATA {}", mem[addr], mem[addr]);
+                    addr += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn print_inst(&self, inst: &Inst) {
+        let marker = if inst.addr == self.vm.ip() { "=>" } else { "  " };
+        let words: Vec<String> = inst.words().iter().map(|w| w.to_string()).collect();
+        println!("{marker} {:04}  {:<24} {inst}", inst.addr, words.join(","));
+    }
+}
+
+// Describes what an executed instruction did
+fn describe(step: &Step) -> String {
+    let text = Inst::decode(&step.words(), 0).map_or(step.word.to_string(), |inst| inst.to_string());
+    let mut line = format!("{:04}  {text}", step.ip);
+    if let Some((addr, value)) = step.write {
+        line.push_str(&format!("    [{addr}] <- {value}"));
+    }
+    if let Some(value) = step.input {
+        line.push_str(&format!("    in {value}"));
+    }
+    if let Some(value) = step.output {
+        line.push_str(&format!("    out {value}"));
+    }
+    if step.next_ip != step.ip + step.arity + 1 {
+        line.push_str(&format!("    -> {:04}", step.next_ip));
+    }
+    line
+}
+
+fn num<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("invalid number '{text}'"))
+}
+
+fn arg_or(args: &[&str], idx: usize, default: usize) -> Result<usize, String> {
+    args.get(idx).map_or(Ok(default), |arg| num(arg))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(path) = args.get(1) else {
+        eprintln!("usage: intcode-dbg <program file> [input ...]");
+        std::process::exit(2);
+    };
+
+    let text = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("cannot read {path}: {err}");
+        std::process::exit(1);
+    });
+    let prog = Machine::parse(text.trim()).unwrap_or_else(|err| {
+        eprintln!("cannot parse {path}: {err}");
+        std::process::exit(1);
+    });
+
+    let mut dbg = Debugger::new(prog);
+    let inputs: Vec<Word> = args[2..].iter().map(|arg| num(arg)).collect::<Result<_, _>>().unwrap_or_else(|err| {
+        eprintln!("bad input: {err}");
+        std::process::exit(2);
+    });
+    dbg.vm.input_ext(&inputs);
+    println!("loaded {} cells from {path}, type help for commands", dbg.prog.len());
+    dbg.show_at(0, 5);
+
+    let stdin = io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("(icdb) ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            line = last.clone();
+        } else {
+            last = line.clone();
+        }
+
+        match dbg.exec(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => println!("error: {err}"),
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use crate::utils::Machine;
+use crate::utils::{Budget, Goal, Machine, MachineState, Program, Solver, Word, batch_find};
+
+const TARGET: Word = 19690720;
+// Far more than any candidate needs, a noun and verb that loop forever are skipped
+const FUEL: u64 = 10_000;
 
 #[aoc_generator(day2)]
-pub fn input_generator(input: &str) -> Vec<isize> {
+pub fn input_generator(input: &str) -> Program {
     input
         .split(',')
         .map(|line| line.parse().unwrap())
@@ -9,30 +13,69 @@ pub fn input_generator(input: &str) -> Vec<isize> {
 }
 
 #[aoc(day2, part1)]
-pub fn solve_part1(input: &Vec<isize>) -> usize {
+pub fn solve_part1(input: &Program) -> usize {
     let mut vm = Machine::new(input);
 
     // 1202 program alarm
     vm.inject(1, 12);
     vm.inject(2, 2);
 
-    vm.run();
+    vm.run().unwrap();
 
     vm.read(0) as usize
 }
 
 #[aoc(day2, part2)]
-pub fn solve_part2(input: &Vec<isize>) -> usize {
-    let target = 19690720;
+pub fn solve_part2(input: &Program) -> usize {
+    search(input, true)
+}
+
+// Same search without the decoded instruction cache, for benchmarking
+#[aoc(day2, part2, uncached)]
+pub fn solve_part2_uncached(input: &Program) -> usize {
+    search(input, false)
+}
+
+// Same search with the candidates spread over all cores
+#[aoc(day2, part2, parallel)]
+pub fn solve_part2_parallel(input: &Program) -> usize {
+    let pairs: Vec<(Word, Word)> = (0..100).flat_map(|noun| (0..100).map(move |verb| (noun, verb))).collect();
+
+    batch_find(pairs, |(noun, verb)| {
+        let mut vm = Machine::new(input);
+        vm.inject(1, noun);
+        vm.inject(2, verb);
+        vm.refuel(Budget::Instructions(FUEL));
+        (vm.run() == Ok(MachineState::Halted) && vm.read(0) == TARGET).then_some((100 * noun + verb) as usize)
+    })
+    .expect("No solution!")
+}
+
+// Treats the noun and verb as symbols. The result is linear in both, so it is
+// solved directly from one run and confirmed by another, instead of trying
+// every pair.
+#[aoc(day2, part2, symbolic)]
+pub fn solve_part2_symbolic(input: &Program) -> usize {
+    let mut solver = Solver::new(input);
+    let noun = solver.cell(1, 0..=99);
+    let verb = solver.cell(2, 0..=99);
+    solver.fuel(FUEL);
+
+    let values = solver.solve(Goal::Cell(0), TARGET).expect("No solution!");
+
+    (100 * values[noun] + values[verb]) as usize
+}
+
+fn search(input: &Program, cached: bool) -> usize {
     let mut vm = Machine::new(input);
+    vm.cache_decoding(cached);
 
     for noun in 0..100 {
         for verb in 0..100 {
             vm.inject(1, noun);
             vm.inject(2, verb);
-            vm.run();
-
-            if vm.read(0) == target {
+            vm.refuel(Budget::Instructions(FUEL));
+            if vm.run() == Ok(MachineState::Halted) && vm.read(0) == TARGET {
                 return (100 * noun + verb) as usize;
             } else {
                 vm.reboot(input);
@@ -1,7 +1,7 @@
-use crate::utils::Machine;
+use crate::utils::{Machine, Program, Word};
 
 #[aoc_generator(day5)]
-pub fn input_generator(input: &str) -> Vec<isize> {
+pub fn input_generator(input: &str) -> Program {
     input
         .split(',')
         .map(|line| line.parse().unwrap())
@@ -9,21 +9,48 @@ pub fn input_generator(input: &str) -> Vec<isize> {
 }
 
 #[aoc(day5, part1)]
-pub fn solve_part1(input: &Vec<isize>) -> isize {
+pub fn solve_part1(input: &Program) -> Word {
     let mut vm = Machine::new(input);
     vm.input_ext(&[1]);
 
-    vm.run();
+    vm.run().unwrap();
 
-    vm.read_last()
+    vm.read_last().unwrap()
 }
 
 #[aoc(day5, part2)]
-pub fn solve_part2(input: &Vec<isize>) -> isize {
+pub fn solve_part2(input: &Program) -> Word {
     let mut vm = Machine::new(input);
     vm.input_ext(&[5]);
 
-    vm.run();
+    vm.run().unwrap();
 
-    vm.read_last()
-}
\ No newline at end of file
+    vm.read_last().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assemble;
+
+    #[test]
+    fn part2_test1() {
+        let test_input = assemble("
+                IN  [x]
+                EQ  [x], [eight], [x]
+                OUT [x]
+                HLT
+            x:  data -1
+            eight:
+                data 8
+        ").unwrap();
+        assert_eq!(test_input, vec![3,9,8,9,10,9,4,9,99,-1,8]);
+
+        for (input, expected) in [(8, 1), (7, 0)] {
+            let mut vm = Machine::new(&test_input);
+            vm.input_ext(&[input]);
+            vm.run().unwrap();
+            assert_eq!(vm.read_last().unwrap(), expected);
+        }
+    }
+}
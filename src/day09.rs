@@ -1,26 +1,26 @@
-use crate::utils::{Machine, Program};
+use crate::utils::{Arithmetic, Machine, Program};
 
 #[aoc_generator(day9)]
 pub fn input_generator(input: &str) -> Program {
-    Machine::parse(input)
+    Machine::parse(input).unwrap()
 }
 
 #[aoc(day9, part1)]
 pub fn solve_part1(input: &Program) -> usize {
     let mut vm = Machine::new(&input);
-    
+    vm.set_arithmetic(Arithmetic::Checked);
     vm.input_ext(&vec![1]);
-    vm.run();
-    vm.read_last() as usize
+    vm.run().unwrap();
+    vm.read_last().unwrap() as usize
 }
 
 #[aoc(day9, part2)]
 pub fn solve_part2(input: &Program) -> usize {
     let mut vm = Machine::new(&input);
-    
+    vm.set_arithmetic(Arithmetic::Checked);
     vm.input_ext(&vec![2]);
-    vm.run();
-    vm.read_last() as usize
+    vm.run().unwrap();
+    vm.read_last().unwrap() as usize
 }
 
 #[cfg(test)]
@@ -31,7 +31,7 @@ mod tests {
     fn part1_test1() {
         let test_input: Program = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
         let mut vm = Machine::new(&test_input);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.dump_output(), &test_input)
     }
 
@@ -39,15 +39,15 @@ mod tests {
     fn part1_test2() {
         let test_input: Program = vec![1102,34915192,34915192,7,4,7,99,0];
         let mut vm = Machine::new(&test_input);
-        vm.run();
-        assert_eq!(vm.dump_output(), &vec![(34915192_isize * 34915192_isize)])
+        vm.run().unwrap();
+        assert_eq!(vm.dump_output(), &vec![34915192 * 34915192])
     }
 
     #[test]
     fn part1_test3() {
         let test_input: Program = vec![104,1125899906842624,99];
         let mut vm = Machine::new(&test_input);
-        vm.run();
-        assert_eq!(vm.dump_output(), &vec![1125899906842624_isize])
+        vm.run().unwrap();
+        assert_eq!(vm.dump_output(), &vec![1125899906842624])
     }
 }
\ No newline at end of file
@@ -0,0 +1,410 @@
+use std::fmt::Write;
+use super::disasm::{Inst, disassemble};
+use super::intcode::{Machine, MachineState, Word};
+
+// Ahead-of-time translation of Intcode into Rust. `translate` turns a program
+// into a function with a `match ip` state machine, one arm per instruction the
+// static disassembly reaches. There is no build script: `intcode-aot` writes
+// the source for a program file, which is saved into the crate and included
+// where `Machine`, `MachineState`, `Native`, `VmError` and `Word` are in scope.
+// The function then runs a `Machine` like `run` does:
+//
+//   include!("day09_native.rs");
+//   let state = day09(&mut vm)?;
+//
+// The puzzle inputs aren't part of the repo, so no day ships translated code;
+// the tests check the translation of example programs against the interpreter.
+//
+// Opcodes and modes are baked into the arms, operands are read from memory.
+// Native code hands over to the interpreter:
+// - for the whole run, when the machine has probes, a trace, a fuel budget or
+//   checked arithmetic, or its code differs from the translated program, e.g.
+//   after the host injected into it
+// - for the rest of the run, once the program writes into an instruction word
+// - until it reaches translated code again, after jumping anywhere else
+// - for one instruction, to raise a fault exactly as the interpreter would
+
+// What a native run returns, as Machine::run
+pub type Outcome = Result<MachineState, crate::utils::VmError>;
+
+// Translated instruction words as (address, word), sorted by address
+pub type Words = &'static [(usize, Word)];
+
+// A machine being run by translated code
+pub struct Native<'a> {
+    vm: &'a mut Machine,
+    words: Words,
+    dirty: bool, // An instruction word was overwritten
+    pub ip: usize,
+    pub rb: Word,
+}
+
+impl<'a> Native<'a> {
+    // Runs translated code, one instruction per call of `step`, until it stops the machine
+    pub fn run<F: FnMut(&mut Self) -> Result<(), Outcome>>(vm: &'a mut Machine, words: Words, mut step: F) -> Outcome {
+        let runnable = matches!(
+            vm.state(),
+            MachineState::Running
+                | MachineState::AwaitingInput
+                | MachineState::OutputReady
+                | MachineState::OutputFull
+                | MachineState::OutOfFuel
+        );
+        if !runnable || vm.is_observed() || words.iter().any(|&(addr, word)| vm.read(addr) != word) {
+            return vm.run();
+        }
+
+        let mut n = Native { ip: vm.ip(), rb: vm.rb(), vm, words, dirty: false };
+        loop {
+            if let Err(outcome) = step(&mut n) {
+                return outcome;
+            }
+        }
+    }
+
+    // Value of a parameter
+    #[inline]
+    pub fn load(&mut self, ip: usize, param: usize, mode: Word) -> Result<Word, Outcome> {
+        if mode == 1 {
+            return Ok(self.vm.read(ip + param));
+        }
+        let addr = self.dest(ip, param, mode)?;
+        Ok(self.vm.read_mem(addr))
+    }
+
+    // Address of a position or relative parameter
+    #[inline]
+    pub fn dest(&mut self, ip: usize, param: usize, mode: Word) -> Result<usize, Outcome> {
+        let raw = self.vm.read(ip + param);
+        let addr = if mode == 2 { self.rb.wrapping_add(raw) } else { raw };
+        match usize::try_from(addr) {
+            Ok(addr) if self.vm.memory().in_bounds(addr) => Ok(addr),
+            _ => Err(self.hand_over(ip)),
+        }
+    }
+
+    #[inline]
+    pub fn store(&mut self, addr: usize, value: Word) {
+        self.dirty |= changes(self.words, addr, value);
+        self.vm.write_mem(addr, value);
+    }
+
+    #[inline]
+    pub fn input(&mut self, ip: usize) -> Result<Word, Outcome> {
+        match self.vm.next_input() {
+            Some(value) => Ok(value),
+            None => Err(self.stop(ip, MachineState::AwaitingInput)),
+        }
+    }
+
+    // Stops if the bounded output queue is full
+    #[inline]
+    pub fn check_output(&mut self, ip: usize) -> Result<(), Outcome> {
+        if self.vm.output_full() {
+            return Err(self.stop(ip, MachineState::OutputFull));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn output(&mut self, value: Word) {
+        self.vm.emit(value);
+    }
+
+    // Moves past the instruction at ip, halting if that runs off the end of memory
+    #[inline]
+    pub fn advance(&mut self, ip: usize, size: usize, opcode: Word) -> Result<(), Outcome> {
+        self.vm.count(opcode);
+        if ip + size >= self.vm.memory().len() {
+            return Err(self.stop(ip, MachineState::Halted));
+        }
+        self.ip = ip + size;
+        if self.dirty {
+            return Err(self.hand_over(self.ip));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn jump(&mut self, ip: usize, target: Word, opcode: Word) -> Result<(), Outcome> {
+        let Ok(target) = usize::try_from(target) else {
+            return Err(self.hand_over(ip));
+        };
+        self.vm.count(opcode);
+        self.ip = target;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn halt(&mut self, ip: usize) -> Result<(), Outcome> {
+        self.vm.count(99);
+        Err(self.stop(ip, MachineState::Halted))
+    }
+
+    // Interprets from ip until execution is back on translated code
+    pub fn interpret(&mut self, ip: usize) -> Result<(), Outcome> {
+        self.vm.set_regs(ip, self.rb, MachineState::Running);
+        let words = self.words;
+        let mut dirty = false;
+        let state = self.vm.run_until(|vm| {
+            dirty |= vm.last_step().write.is_some_and(|(addr, value)| changes(words, addr, value));
+            dirty || words.binary_search_by_key(&vm.ip(), |&(addr, _)| addr).is_ok()
+        });
+
+        match state {
+            Ok(MachineState::Running) if dirty => Err(self.vm.run()),
+            Ok(MachineState::Running) => {
+                self.ip = self.vm.ip();
+                self.rb = self.vm.rb();
+                Ok(())
+            }
+            other => Err(other),
+        }
+    }
+
+    fn stop(&mut self, ip: usize, state: MachineState) -> Outcome {
+        self.vm.set_regs(ip, self.rb, state);
+        Ok(state)
+    }
+
+    // Lets the interpreter take over at ip, for good
+    fn hand_over(&mut self, ip: usize) -> Outcome {
+        self.vm.set_regs(ip, self.rb, MachineState::Running);
+        self.vm.run()
+    }
+}
+
+// Whether writing a value changes a translated instruction word
+fn changes(words: Words, addr: usize, value: Word) -> bool {
+    words.last().is_some_and(|&(last, _)| addr <= last)
+        && words.binary_search_by_key(&addr, |&(addr, _)| addr).is_ok_and(|idx| words[idx].1 != value)
+}
+
+// Translates a program into the source of a Rust function with the given name
+pub fn translate(prog: &[Word], name: &str) -> String {
+    let dis = disassemble(prog);
+    let insts: Vec<&Inst> = dis.instructions().collect();
+
+    let mut src = String::new();
+    writeln!(src, "// Translated from an Intcode program of {} cells, {} instructions.", prog.len(), insts.len()).unwrap();
+    writeln!(src, "// Generated code, do not edit. Needs Machine, MachineState, Native, VmError and Word in scope.").unwrap();
+    writeln!(src, "pub fn {name}(vm: &mut Machine) -> Result<MachineState, VmError> {{").unwrap();
+    writeln!(src, "    const WORDS: &[(usize, Word)] = &[").unwrap();
+    for row in insts.chunks(8) {
+        let words: Vec<String> = row.iter().map(|inst| format!("({}, {})", inst.addr, inst.word)).collect();
+        writeln!(src, "        {},", words.join(", ")).unwrap();
+    }
+    writeln!(src, "    ];").unwrap();
+    writeln!(src).unwrap();
+    writeln!(src, "    Native::run(vm, WORDS, |n| match n.ip {{").unwrap();
+    for inst in &insts {
+        writeln!(src, "        // {inst}").unwrap();
+        writeln!(src, "        {} => {{", inst.addr).unwrap();
+        for line in arm(inst) {
+            writeln!(src, "            {line}").unwrap();
+        }
+        writeln!(src, "        }}").unwrap();
+    }
+    writeln!(src, "        ip => n.interpret(ip),").unwrap();
+    writeln!(src, "    }})").unwrap();
+    writeln!(src, "}}").unwrap();
+    src
+}
+
+// Statements executing an instruction, the last one is the arm's value
+fn arm(inst: &Inst) -> Vec<String> {
+    let (a, op, [m1, m2, m3]) = (inst.addr, inst.info.opcode, inst.modes);
+    let load = |param: usize, mode: Word| format!("n.load({a}, {param}, {mode})?");
+
+    match op {
+        1 | 2 | 7 | 8 => {
+            let value = match op {
+                1 => "x.wrapping_add(y)",
+                2 => "x.wrapping_mul(y)",
+                7 => "(x < y) as Word",
+                _ => "(x == y) as Word",
+            };
+            vec![
+                format!("let c = n.dest({a}, 3, {m3})?;"),
+                format!("let x = {};", load(1, m1)),
+                format!("let y = {};", load(2, m2)),
+                format!("n.store(c, {value});"),
+                format!("n.advance({a}, 4, {op})"),
+            ]
+        }
+        3 => vec![
+            format!("let c = n.dest({a}, 1, {m1})?;"),
+            format!("let x = n.input({a})?;"),
+            "n.store(c, x);".to_string(),
+            format!("n.advance({a}, 2, {op})"),
+        ],
+        4 => vec![
+            format!("n.check_output({a})?;"),
+            format!("let x = {};", load(1, m1)),
+            "n.output(x);".to_string(),
+            format!("n.advance({a}, 2, {op})"),
+        ],
+        5 | 6 => vec![
+            format!("let x = {};", load(1, m1)),
+            format!("if x {} 0 {{", if op == 5 { "!=" } else { "==" }),
+            format!("    let t = {};", load(2, m2)),
+            format!("    n.jump({a}, t, {op})"),
+            "} else {".to_string(),
+            format!("    n.advance({a}, 3, {op})"),
+            "}".to_string(),
+        ],
+        9 => vec![
+            format!("let x = {};", load(1, m1)),
+            "n.rb = n.rb.wrapping_add(x);".to_string(),
+            format!("n.advance({a}, 2, {op})"),
+        ],
+        _ => vec![format!("n.halt({a})")],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Memory, Program, assemble};
+
+    mod golden {
+        use crate::utils::{Machine, MachineState, Native, VmError, Word};
+        include!("aot_golden.rs");
+    }
+
+    // Recursive Fibonacci in the style of a day 9 program: reads n, prints
+    // fib(n). Each call gets a frame of four cells on a stack addressed
+    // through the relative base: return address, n, fib(n - 1), result.
+    const FIB: &str = "
+            RBX #stack
+            IN  rb+1
+            ADD #done, #0, rb+0
+            JZ  #0, #fib
+    done:   OUT rb+3
+            HLT
+
+    fib:    LT  rb+1, #2, rb+2
+            JZ  rb+2, #split
+            ADD rb+1, #0, rb+3
+            JZ  #0, rb+0
+    split:  ADD #back1, #0, rb+4
+            ADD rb+1, #-1, rb+5
+            RBX #4
+            JZ  #0, #fib
+    back1:  RBX #-4
+            ADD rb+7, #0, rb+2
+            ADD #back2, #0, rb+4
+            ADD rb+1, #-2, rb+5
+            RBX #4
+            JZ  #0, #fib
+    back2:  RBX #-4
+            ADD rb+2, rb+7, rb+3
+            JZ  #0, rb+0
+    stack:  data 0
+    ";
+
+    // Example programs from days 5, 7 and 9, a self-modifying one and a full
+    // recursive one, in the order they appear in the golden file
+    fn examples() -> Vec<(&'static str, Program)> {
+        vec![
+            ("day05_compare", vec![3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99]),
+            ("day07_feedback", vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5]),
+            ("day09_quine", vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99]),
+            // Rewrites its ADD into a MUL after the first pass
+            ("patched", vec![1101,2,3,21,4,21,1005,22,20,1101,1,0,22,1101,1102,0,0,1105,1,0,99,0,0]),
+            ("day09_fib", assemble(FIB).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn golden_is_current() {
+        let src: Vec<String> = examples().iter().map(|(name, prog)| translate(prog, name)).collect();
+        let src = src.join("\n");
+        if std::env::var_os("AOT_BLESS").is_some() {
+            std::fs::write(concat!(env!("CARGO_MANIFEST_DIR"), "/src/utils/aot_golden.rs"), &src).unwrap();
+        }
+        assert_eq!(src, include_str!("aot_golden.rs"), "run with AOT_BLESS=1 to regenerate");
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let prog = &examples()[0].1;
+        for input in 6..=10 {
+            let mut interp = Machine::new(prog);
+            let mut native = Machine::new(prog);
+            interp.input_ext(&[input]);
+            native.input_ext(&[input]);
+            assert_eq!(golden::day05_compare(&mut native), interp.run());
+            assert_eq!(native.dump_output(), interp.dump_output());
+            assert_eq!(native.ip(), interp.ip());
+            assert_eq!(native.counters(), interp.counters());
+        }
+
+        let prog = &examples()[2].1;
+        let mut vm = Machine::with_memory(Memory::paged(prog));
+        assert_eq!(golden::day09_quine(&mut vm), Ok(MachineState::Halted));
+        assert_eq!(vm.dump_output(), prog);
+    }
+
+    #[test]
+    fn recursive_program_matches_interpreter() {
+        let prog = &examples()[4].1;
+        for input in [0, 1, 2, 10, 20] {
+            let mut interp = Machine::new(prog);
+            let mut native = Machine::new(prog);
+            interp.input_ext(&[input]);
+            native.input_ext(&[input]);
+            assert_eq!(golden::day09_fib(&mut native), interp.run());
+            assert_eq!(native.dump_output(), interp.dump_output());
+            assert_eq!(native.rb(), interp.rb());
+            assert_eq!(native.counters(), interp.counters());
+            assert_eq!(native.memory().prefix(), interp.memory().prefix());
+        }
+
+        let mut vm = Machine::new(prog);
+        vm.input_ext(&[20]);
+        golden::day09_fib(&mut vm).unwrap();
+        assert_eq!(vm.dump_output(), &vec![6765]);
+    }
+
+    #[test]
+    fn resumes_after_input() {
+        let prog = &examples()[1].1;
+        let mut amps: Vec<Machine> = [9, 8, 7, 6, 5].iter().map(|&phase| {
+            let mut vm = Machine::new(prog);
+            vm.input_ext(&[phase]);
+            vm
+        }).collect();
+        amps[0].input_ext(&[0]);
+
+        let mut last = 0;
+        loop {
+            for idx in 0..5 {
+                let state = golden::day07_feedback(&mut amps[idx]).unwrap();
+                let output = amps[idx].take_output();
+                last = output.last().copied().unwrap_or(last);
+                amps[(idx + 1) % 5].input_ext(&output);
+                if idx == 4 && state == MachineState::Halted {
+                    assert_eq!(last, 139629729);
+                    return;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn falls_back_on_self_modification() {
+        let prog = &examples()[3].1;
+        let mut interp = Machine::new(prog);
+        let mut native = Machine::new(prog);
+        assert_eq!(golden::patched(&mut native), interp.run());
+        assert_eq!(native.dump_output(), &vec![5, 6]);
+        assert_eq!(native.dump_output(), interp.dump_output());
+
+        // Code changed by the host is left to the interpreter
+        let mut injected = Machine::new(prog);
+        injected.inject(0, 1102);
+        assert_eq!(golden::patched(&mut injected), Ok(MachineState::Halted));
+        assert_eq!(injected.dump_output(), &vec![6, 6]);
+    }
+}
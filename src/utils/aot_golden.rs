@@ -0,0 +1,450 @@
+// Translated from an Intcode program of 47 cells, 15 instructions.
+// Generated code, do not edit. Needs Machine, MachineState, Native, VmError and Word in scope.
+pub fn day05_compare(vm: &mut Machine) -> Result<MachineState, VmError> {
+    const WORDS: &[(usize, Word)] = &[
+        (0, 3), (2, 1008), (6, 1005), (9, 107), (13, 1006), (16, 1106), (22, 1002), (26, 4),
+        (28, 1105), (31, 104), (33, 1105), (36, 1101), (40, 4), (42, 1105), (46, 99),
+    ];
+
+    Native::run(vm, WORDS, |n| match n.ip {
+        // IN  [21]
+        0 => {
+            let c = n.dest(0, 1, 0)?;
+            let x = n.input(0)?;
+            n.store(c, x);
+            n.advance(0, 2, 3)
+        }
+        // EQ  [21], #8, [20]
+        2 => {
+            let c = n.dest(2, 3, 0)?;
+            let x = n.load(2, 1, 0)?;
+            let y = n.load(2, 2, 1)?;
+            n.store(c, (x == y) as Word);
+            n.advance(2, 4, 8)
+        }
+        // JNZ [20], #22
+        6 => {
+            let x = n.load(6, 1, 0)?;
+            if x != 0 {
+                let t = n.load(6, 2, 1)?;
+                n.jump(6, t, 5)
+            } else {
+                n.advance(6, 3, 5)
+            }
+        }
+        // LT  #8, [21], [20]
+        9 => {
+            let c = n.dest(9, 3, 0)?;
+            let x = n.load(9, 1, 1)?;
+            let y = n.load(9, 2, 0)?;
+            n.store(c, (x < y) as Word);
+            n.advance(9, 4, 7)
+        }
+        // JZ  [20], #31
+        13 => {
+            let x = n.load(13, 1, 0)?;
+            if x == 0 {
+                let t = n.load(13, 2, 1)?;
+                n.jump(13, t, 6)
+            } else {
+                n.advance(13, 3, 6)
+            }
+        }
+        // JZ  #0, #36
+        16 => {
+            let x = n.load(16, 1, 1)?;
+            if x == 0 {
+                let t = n.load(16, 2, 1)?;
+                n.jump(16, t, 6)
+            } else {
+                n.advance(16, 3, 6)
+            }
+        }
+        // MUL [21], #125, [20]
+        22 => {
+            let c = n.dest(22, 3, 0)?;
+            let x = n.load(22, 1, 0)?;
+            let y = n.load(22, 2, 1)?;
+            n.store(c, x.wrapping_mul(y));
+            n.advance(22, 4, 2)
+        }
+        // OUT [20]
+        26 => {
+            n.check_output(26)?;
+            let x = n.load(26, 1, 0)?;
+            n.output(x);
+            n.advance(26, 2, 4)
+        }
+        // JNZ #1, #46
+        28 => {
+            let x = n.load(28, 1, 1)?;
+            if x != 0 {
+                let t = n.load(28, 2, 1)?;
+                n.jump(28, t, 5)
+            } else {
+                n.advance(28, 3, 5)
+            }
+        }
+        // OUT #999
+        31 => {
+            n.check_output(31)?;
+            let x = n.load(31, 1, 1)?;
+            n.output(x);
+            n.advance(31, 2, 4)
+        }
+        // JNZ #1, #46
+        33 => {
+            let x = n.load(33, 1, 1)?;
+            if x != 0 {
+                let t = n.load(33, 2, 1)?;
+                n.jump(33, t, 5)
+            } else {
+                n.advance(33, 3, 5)
+            }
+        }
+        // ADD #1000, #1, [20]
+        36 => {
+            let c = n.dest(36, 3, 0)?;
+            let x = n.load(36, 1, 1)?;
+            let y = n.load(36, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(36, 4, 1)
+        }
+        // OUT [20]
+        40 => {
+            n.check_output(40)?;
+            let x = n.load(40, 1, 0)?;
+            n.output(x);
+            n.advance(40, 2, 4)
+        }
+        // JNZ #1, #46
+        42 => {
+            let x = n.load(42, 1, 1)?;
+            if x != 0 {
+                let t = n.load(42, 2, 1)?;
+                n.jump(42, t, 5)
+            } else {
+                n.advance(42, 3, 5)
+            }
+        }
+        // HLT
+        46 => {
+            n.halt(46)
+        }
+        ip => n.interpret(ip),
+    })
+}
+
+// Translated from an Intcode program of 29 cells, 9 instructions.
+// Generated code, do not edit. Needs Machine, MachineState, Native, VmError and Word in scope.
+pub fn day07_feedback(vm: &mut Machine) -> Result<MachineState, VmError> {
+    const WORDS: &[(usize, Word)] = &[
+        (0, 3), (2, 1001), (6, 3), (8, 1002), (12, 1), (16, 4), (18, 1001), (22, 1005),
+        (25, 99),
+    ];
+
+    Native::run(vm, WORDS, |n| match n.ip {
+        // IN  [26]
+        0 => {
+            let c = n.dest(0, 1, 0)?;
+            let x = n.input(0)?;
+            n.store(c, x);
+            n.advance(0, 2, 3)
+        }
+        // ADD [26], #-4, [26]
+        2 => {
+            let c = n.dest(2, 3, 0)?;
+            let x = n.load(2, 1, 0)?;
+            let y = n.load(2, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(2, 4, 1)
+        }
+        // IN  [27]
+        6 => {
+            let c = n.dest(6, 1, 0)?;
+            let x = n.input(6)?;
+            n.store(c, x);
+            n.advance(6, 2, 3)
+        }
+        // MUL [27], #2, [27]
+        8 => {
+            let c = n.dest(8, 3, 0)?;
+            let x = n.load(8, 1, 0)?;
+            let y = n.load(8, 2, 1)?;
+            n.store(c, x.wrapping_mul(y));
+            n.advance(8, 4, 2)
+        }
+        // ADD [27], [26], [27]
+        12 => {
+            let c = n.dest(12, 3, 0)?;
+            let x = n.load(12, 1, 0)?;
+            let y = n.load(12, 2, 0)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(12, 4, 1)
+        }
+        // OUT [27]
+        16 => {
+            n.check_output(16)?;
+            let x = n.load(16, 1, 0)?;
+            n.output(x);
+            n.advance(16, 2, 4)
+        }
+        // ADD [28], #-1, [28]
+        18 => {
+            let c = n.dest(18, 3, 0)?;
+            let x = n.load(18, 1, 0)?;
+            let y = n.load(18, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(18, 4, 1)
+        }
+        // JNZ [28], #6
+        22 => {
+            let x = n.load(22, 1, 0)?;
+            if x != 0 {
+                let t = n.load(22, 2, 1)?;
+                n.jump(22, t, 5)
+            } else {
+                n.advance(22, 3, 5)
+            }
+        }
+        // HLT
+        25 => {
+            n.halt(25)
+        }
+        ip => n.interpret(ip),
+    })
+}
+
+// Translated from an Intcode program of 16 cells, 6 instructions.
+// Generated code, do not edit. Needs Machine, MachineState, Native, VmError and Word in scope.
+pub fn day09_quine(vm: &mut Machine) -> Result<MachineState, VmError> {
+    const WORDS: &[(usize, Word)] = &[
+        (0, 109), (2, 204), (4, 1001), (8, 1008), (12, 1006), (15, 99),
+    ];
+
+    Native::run(vm, WORDS, |n| match n.ip {
+        // RBX #1
+        0 => {
+            let x = n.load(0, 1, 1)?;
+            n.rb = n.rb.wrapping_add(x);
+            n.advance(0, 2, 9)
+        }
+        // OUT rb-1
+        2 => {
+            n.check_output(2)?;
+            let x = n.load(2, 1, 2)?;
+            n.output(x);
+            n.advance(2, 2, 4)
+        }
+        // ADD [100], #1, [100]
+        4 => {
+            let c = n.dest(4, 3, 0)?;
+            let x = n.load(4, 1, 0)?;
+            let y = n.load(4, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(4, 4, 1)
+        }
+        // EQ  [100], #16, [101]
+        8 => {
+            let c = n.dest(8, 3, 0)?;
+            let x = n.load(8, 1, 0)?;
+            let y = n.load(8, 2, 1)?;
+            n.store(c, (x == y) as Word);
+            n.advance(8, 4, 8)
+        }
+        // JZ  [101], #0
+        12 => {
+            let x = n.load(12, 1, 0)?;
+            if x == 0 {
+                let t = n.load(12, 2, 1)?;
+                n.jump(12, t, 6)
+            } else {
+                n.advance(12, 3, 6)
+            }
+        }
+        // HLT
+        15 => {
+            n.halt(15)
+        }
+        ip => n.interpret(ip),
+    })
+}
+
+// Translated from an Intcode program of 23 cells, 7 instructions.
+// Generated code, do not edit. Needs Machine, MachineState, Native, VmError and Word in scope.
+pub fn patched(vm: &mut Machine) -> Result<MachineState, VmError> {
+    const WORDS: &[(usize, Word)] = &[
+        (0, 1101), (4, 4), (6, 1005), (9, 1101), (13, 1101), (17, 1105), (20, 99),
+    ];
+
+    Native::run(vm, WORDS, |n| match n.ip {
+        // ADD #2, #3, [21]
+        0 => {
+            let c = n.dest(0, 3, 0)?;
+            let x = n.load(0, 1, 1)?;
+            let y = n.load(0, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(0, 4, 1)
+        }
+        // OUT [21]
+        4 => {
+            n.check_output(4)?;
+            let x = n.load(4, 1, 0)?;
+            n.output(x);
+            n.advance(4, 2, 4)
+        }
+        // JNZ [22], #20
+        6 => {
+            let x = n.load(6, 1, 0)?;
+            if x != 0 {
+                let t = n.load(6, 2, 1)?;
+                n.jump(6, t, 5)
+            } else {
+                n.advance(6, 3, 5)
+            }
+        }
+        // ADD #1, #0, [22]
+        9 => {
+            let c = n.dest(9, 3, 0)?;
+            let x = n.load(9, 1, 1)?;
+            let y = n.load(9, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(9, 4, 1)
+        }
+        // ADD #1102, #0, [0]
+        13 => {
+            let c = n.dest(13, 3, 0)?;
+            let x = n.load(13, 1, 1)?;
+            let y = n.load(13, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(13, 4, 1)
+        }
+        // JNZ #1, #0
+        17 => {
+            let x = n.load(17, 1, 1)?;
+            if x != 0 {
+                let t = n.load(17, 2, 1)?;
+                n.jump(17, t, 5)
+            } else {
+                n.advance(17, 3, 5)
+            }
+        }
+        // HLT
+        20 => {
+            n.halt(20)
+        }
+        ip => n.interpret(ip),
+    })
+}
+
+// Translated from an Intcode program of 70 cells, 12 instructions.
+// Generated code, do not edit. Needs Machine, MachineState, Native, VmError and Word in scope.
+pub fn day09_fib(vm: &mut Machine) -> Result<MachineState, VmError> {
+    const WORDS: &[(usize, Word)] = &[
+        (0, 109), (2, 203), (4, 21101), (8, 1106), (14, 21207), (18, 1206), (21, 21201), (25, 2106),
+        (28, 21101), (32, 21201), (36, 109), (38, 1106),
+    ];
+
+    Native::run(vm, WORDS, |n| match n.ip {
+        // RBX #69
+        0 => {
+            let x = n.load(0, 1, 1)?;
+            n.rb = n.rb.wrapping_add(x);
+            n.advance(0, 2, 9)
+        }
+        // IN  rb+1
+        2 => {
+            let c = n.dest(2, 1, 2)?;
+            let x = n.input(2)?;
+            n.store(c, x);
+            n.advance(2, 2, 3)
+        }
+        // ADD #11, #0, rb+0
+        4 => {
+            let c = n.dest(4, 3, 2)?;
+            let x = n.load(4, 1, 1)?;
+            let y = n.load(4, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(4, 4, 1)
+        }
+        // JZ  #0, #14
+        8 => {
+            let x = n.load(8, 1, 1)?;
+            if x == 0 {
+                let t = n.load(8, 2, 1)?;
+                n.jump(8, t, 6)
+            } else {
+                n.advance(8, 3, 6)
+            }
+        }
+        // LT  rb+1, #2, rb+2
+        14 => {
+            let c = n.dest(14, 3, 2)?;
+            let x = n.load(14, 1, 2)?;
+            let y = n.load(14, 2, 1)?;
+            n.store(c, (x < y) as Word);
+            n.advance(14, 4, 7)
+        }
+        // JZ  rb+2, #28
+        18 => {
+            let x = n.load(18, 1, 2)?;
+            if x == 0 {
+                let t = n.load(18, 2, 1)?;
+                n.jump(18, t, 6)
+            } else {
+                n.advance(18, 3, 6)
+            }
+        }
+        // ADD rb+1, #0, rb+3
+        21 => {
+            let c = n.dest(21, 3, 2)?;
+            let x = n.load(21, 1, 2)?;
+            let y = n.load(21, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(21, 4, 1)
+        }
+        // JZ  #0, rb+0
+        25 => {
+            let x = n.load(25, 1, 1)?;
+            if x == 0 {
+                let t = n.load(25, 2, 2)?;
+                n.jump(25, t, 6)
+            } else {
+                n.advance(25, 3, 6)
+            }
+        }
+        // ADD #41, #0, rb+4
+        28 => {
+            let c = n.dest(28, 3, 2)?;
+            let x = n.load(28, 1, 1)?;
+            let y = n.load(28, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(28, 4, 1)
+        }
+        // ADD rb+1, #-1, rb+5
+        32 => {
+            let c = n.dest(32, 3, 2)?;
+            let x = n.load(32, 1, 2)?;
+            let y = n.load(32, 2, 1)?;
+            n.store(c, x.wrapping_add(y));
+            n.advance(32, 4, 1)
+        }
+        // RBX #4
+        36 => {
+            let x = n.load(36, 1, 1)?;
+            n.rb = n.rb.wrapping_add(x);
+            n.advance(36, 2, 9)
+        }
+        // JZ  #0, #14
+        38 => {
+            let x = n.load(38, 1, 1)?;
+            if x == 0 {
+                let t = n.load(38, 2, 1)?;
+                n.jump(38, t, 6)
+            } else {
+                n.advance(38, 3, 6)
+            }
+        }
+        ip => n.interpret(ip),
+    })
+}
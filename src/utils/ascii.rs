@@ -0,0 +1,211 @@
+use std::io::{self, BufRead, Write};
+use super::intcode::{Machine, MachineState, Program, VmError, Word};
+
+// What the machine printed next
+enum Printed {
+    Char(char),
+    Value(Word), // Outside the ASCII range, usually an answer
+}
+
+// ASCII console over a machine, for programs that print prompts and read
+// commands as character codes
+#[derive(Debug, Clone)]
+pub struct Console {
+    vm: Machine,
+    line: String,       // Text of the line being collected
+    values: Vec<Word>, // Non-ASCII values printed so far
+}
+
+impl Console {
+    pub fn new(prog: &Program) -> Self {
+        Self::from_machine(Machine::new(prog))
+    }
+
+    // Output sent to a device attached to the machine never reaches the
+    // console, which then reads as if nothing was printed
+    pub fn from_machine(vm: Machine) -> Self {
+        Self { vm, line: String::new(), values: Vec::new() }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.vm
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.vm
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.vm
+    }
+
+    pub fn state(&self) -> MachineState {
+        self.vm.state()
+    }
+
+    // Queues text as input codes
+    pub fn send(&mut self, text: &str) {
+        let codes: Vec<Word> = text.chars().map(|ch| ch as Word).collect();
+        self.vm.input_ext(&codes);
+    }
+
+    // Queues a line of input, adding the newline
+    pub fn send_line(&mut self, line: &str) {
+        self.send(line);
+        self.send("\n");
+    }
+
+    // Runs until a full line is printed and returns it without the newline.
+    // Stops early with the text collected so far when the machine waits for
+    // input or halts, None if there was none.
+    pub fn read_line(&mut self) -> Result<Option<String>, VmError> {
+        while let Some(printed) = self.next()? {
+            match printed {
+                Printed::Char('\n') => return Ok(Some(std::mem::take(&mut self.line))),
+                Printed::Char(ch) => self.line.push(ch),
+                Printed::Value(value) => self.values.push(value),
+            }
+        }
+
+        Ok((!self.line.is_empty()).then(|| std::mem::take(&mut self.line)))
+    }
+
+    // Runs until the machine stops and returns all the text printed
+    pub fn read_all(&mut self) -> Result<String, VmError> {
+        let mut text = std::mem::take(&mut self.line);
+        while let Some(printed) = self.next()? {
+            match printed {
+                Printed::Char(ch) => text.push(ch),
+                Printed::Value(value) => self.values.push(value),
+            }
+        }
+
+        Ok(text)
+    }
+
+    // Non-ASCII values printed so far
+    pub fn values(&self) -> &[Word] {
+        &self.values
+    }
+
+    pub fn take_values(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.values)
+    }
+
+    // The last non-ASCII value, usually the answer
+    pub fn answer(&self) -> Option<Word> {
+        self.values.last().copied()
+    }
+
+    // Passes stdin and stdout through to the machine until it halts or input runs out
+    pub fn interact(&mut self) -> io::Result<MachineState> {
+        self.interact_with(io::stdin().lock(), io::stdout().lock())
+    }
+
+    // Interactive mode over any reader and writer. Non-ASCII values are
+    // printed as numbers on their own line.
+    pub fn interact_with<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<MachineState> {
+        let mut line_start = true;
+        loop {
+            output.write_all(std::mem::take(&mut self.line).as_bytes())?;
+            while let Some(printed) = self.next().map_err(io::Error::other)? {
+                match printed {
+                    Printed::Char(ch) => {
+                        write!(output, "{ch}")?;
+                        line_start = ch == '\n';
+                    }
+                    Printed::Value(value) => {
+                        if !line_start {
+                            writeln!(output)?;
+                        }
+                        writeln!(output, "{value}")?;
+                        self.values.push(value);
+                        line_start = true;
+                    }
+                }
+            }
+            output.flush()?;
+
+            if self.vm.state() != MachineState::AwaitingInput {
+                return Ok(self.vm.state());
+            }
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(self.vm.state());
+            }
+            self.send_line(line.trim_end_matches(['\r', '\n']));
+        }
+    }
+
+    // Runs to the next printed value, None once the machine stops
+    fn next(&mut self) -> Result<Option<Printed>, VmError> {
+        if self.vm.run_until_output()? != MachineState::OutputReady {
+            return Ok(None);
+        }
+
+        let Some(value) = self.vm.pop_output() else {
+            return Ok(None);
+        };
+        Ok(Some(match u8::try_from(value) {
+            Ok(byte) if byte.is_ascii() => Printed::Char(byte as char),
+            _ => Printed::Value(value),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assemble;
+
+    // Asks for a name, echoes it back and prints 1000
+    const GREETER: &str = "
+            RBX #msg
+    print:  OUT rb+0
+            RBX #1
+            JNZ rb+0, #print
+    echo:   IN  [ch]
+            EQ  [ch], #10, [t]
+            JNZ [t], #done
+            OUT [ch]
+            JZ  #0, #echo
+    done:   OUT #1000
+            HLT
+    ch:     data 0
+    t:      data 0
+    msg:    string \"Name?\\n> \"
+            data 0
+    ";
+
+    #[test]
+    fn lines_and_values() {
+        let mut con = Console::new(&assemble(GREETER).unwrap());
+        assert_eq!(con.read_line(), Ok(Some("Name?".to_string())));
+        assert_eq!(con.read_line(), Ok(Some("> ".to_string())));
+        assert_eq!(con.state(), MachineState::AwaitingInput);
+        assert_eq!(con.read_line(), Ok(None));
+
+        con.send_line("bob");
+        assert_eq!(con.read_all(), Ok("bob".to_string()));
+        assert_eq!(con.state(), MachineState::Halted);
+        assert_eq!(con.answer(), Some(1000));
+    }
+
+    #[test]
+    fn interactive_pass_through() {
+        let mut con = Console::new(&assemble(GREETER).unwrap());
+        let mut screen = Vec::new();
+        assert_eq!(con.interact_with("amy\n".as_bytes(), &mut screen).unwrap(), MachineState::Halted);
+        assert_eq!(String::from_utf8(screen).unwrap(), "Name?\n> amy\n1000\n");
+        assert_eq!(con.values(), &[1000]);
+    }
+
+    #[test]
+    fn output_device_is_not_read() {
+        let mut vm = Machine::new(&assemble(GREETER).unwrap());
+        vm.attach_output(crate::utils::Null);
+        let mut con = Console::from_machine(vm);
+        assert_eq!(con.read_line(), Ok(None));
+        assert_eq!(con.read_all(), Ok(String::new()));
+    }
+}
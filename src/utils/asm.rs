@@ -0,0 +1,461 @@
+use std::{collections::HashMap, fmt};
+use super::intcode::{OpInfo, Program, Word};
+
+// Assembly syntax, one statement per line, `;` starts a comment:
+//
+//   label:  MNEMONIC operand, operand, ...
+//           data 1, -2, 'x', label+1
+//           string "text\n"
+//           local name = offset
+//
+// Operands are `[expr]` for position, `#expr` for immediate and `rb+expr` for
+// relative mode. A local names a relative base slot and is used bare, e.g.
+// `ADD count, #1, count`. RBX instructions with an immediate operand are
+// tracked in source order, so a local keeps referring to the same cell after
+// the relative base moves.
+
+// An assembly error at a line and column, both counted from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Ident(String),
+    Int(Word),
+    Str(String),
+    Punct(char),
+}
+
+// An expression as a signed sum of numbers and labels
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(Word, Atom, usize)>, // Sign, atom and column
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Num(Word),
+    Label(String),
+}
+
+// A cell of output, possibly waiting on labels defined later
+#[derive(Debug, Clone)]
+enum Cell {
+    Fixed(Word),
+    Expr(Expr),
+}
+
+// A local slot, remembered with the relative base displacement it was declared at
+#[derive(Debug, Clone, Copy)]
+struct Local {
+    slot: Word,
+    disp: Word,
+}
+
+struct Line<'a> {
+    no: usize,
+    toks: Vec<(Tok, usize)>,
+    pos: usize,
+    src: &'a str,
+}
+
+impl Line<'_> {
+    fn err<T>(&self, col: usize, msg: impl Into<String>) -> Result<T, AsmError> {
+        Err(AsmError { line: self.no, col, msg: msg.into() })
+    }
+
+    // Column of the next token, or just past the end of the line
+    fn col(&self) -> usize {
+        self.toks.get(self.pos).map_or(self.src.trim_end().len() + 1, |(_, col)| *col)
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos).map(|(tok, _)| tok)
+    }
+
+    fn next(&mut self) -> Option<(Tok, usize)> {
+        let tok = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        if self.peek() == Some(&Tok::Punct(ch)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, ch: char) -> Result<(), AsmError> {
+        if self.eat(ch) {
+            Ok(())
+        } else {
+            self.err(self.col(), format!("expected '{ch}'"))
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.toks.len()
+    }
+
+    fn ident(&mut self) -> Result<(String, usize), AsmError> {
+        match self.next() {
+            Some((Tok::Ident(name), col)) => Ok((name, col)),
+            Some((_, col)) => self.err(col, "expected a name"),
+            None => self.err(self.col(), "expected a name"),
+        }
+    }
+
+    // expr := ['-'] atom (('+' | '-') atom)*
+    fn expr(&mut self) -> Result<Expr, AsmError> {
+        let mut terms = Vec::new();
+        let mut sign = if self.eat('-') { -1 } else { 1 };
+        loop {
+            let atom = match self.next() {
+                Some((Tok::Int(n), col)) => (sign, Atom::Num(n), col),
+                Some((Tok::Ident(name), col)) => (sign, Atom::Label(name), col),
+                Some((_, col)) => return self.err(col, "expected a number or label"),
+                None => return self.err(self.col(), "expected a number or label"),
+            };
+            terms.push(atom);
+
+            if self.eat('+') {
+                sign = 1;
+            } else if self.eat('-') {
+                sign = -1;
+            } else {
+                return Ok(Expr { terms });
+            }
+        }
+    }
+
+    // Comma separated list until the end of the line
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, AsmError>) -> Result<Vec<T>, AsmError> {
+        let mut items = Vec::new();
+        if self.at_end() {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.at_end() {
+                return Ok(items);
+            }
+            self.expect(',')?;
+        }
+    }
+}
+
+impl Expr {
+    // Value of an expression that only uses numbers
+    fn constant(&self, line: usize) -> Result<Option<Word>, AsmError> {
+        let mut value = 0;
+        for (sign, atom, col) in &self.terms {
+            match atom {
+                Atom::Num(n) => value = add_term(value, *sign, *n, line, *col)?,
+                Atom::Label(_) => return Ok(None),
+            }
+        }
+
+        Ok(Some(value))
+    }
+
+    fn eval(&self, labels: &HashMap<String, usize>, line: usize) -> Result<Word, AsmError> {
+        let mut value = 0;
+        for (sign, atom, col) in &self.terms {
+            let term = match atom {
+                Atom::Num(n) => *n,
+                Atom::Label(name) => match labels.get(name) {
+                    Some(addr) => *addr as Word,
+                    None => return Err(AsmError { line, col: *col, msg: format!("undefined label '{name}'") }),
+                },
+            };
+            value = add_term(value, *sign, term, line, *col)?;
+        }
+
+        Ok(value)
+    }
+}
+
+// Adds a signed term to the value of an expression, failing on overflow
+fn add_term(value: Word, sign: Word, term: Word, line: usize, col: usize) -> Result<Word, AsmError> {
+    term.checked_mul(sign)
+        .and_then(|term| value.checked_add(term))
+        .ok_or_else(|| AsmError { line, col, msg: "expression overflows a word".to_string() })
+}
+
+// Splits a line into tokens with their columns, stopping at a comment
+fn lex(no: usize, src: &str) -> Result<Vec<(Tok, usize)>, AsmError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut toks = Vec::new();
+    let mut idx = 0;
+    let err = |col: usize, msg: &str| AsmError { line: no, col, msg: msg.to_string() };
+
+    // Reads a possibly escaped character inside a string or character literal
+    let escaped = |idx: &mut usize| -> Option<char> {
+        let ch = *chars.get(*idx)?;
+        *idx += 1;
+        if ch != '\\' {
+            return Some(ch);
+        }
+        let esc = *chars.get(*idx)?;
+        *idx += 1;
+        match esc {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '0' => Some('\0'),
+            other => Some(other),
+        }
+    };
+
+    while idx < chars.len() {
+        let ch = chars[idx];
+        let col = idx + 1;
+        if ch == ';' {
+            break;
+        } else if ch.is_whitespace() {
+            idx += 1;
+        } else if ch.is_ascii_digit() {
+            let start = idx;
+            while idx < chars.len() && chars[idx].is_ascii_alphanumeric() {
+                idx += 1;
+            }
+            let text: String = chars[start..idx].iter().collect();
+            let value = text.parse().map_err(|_| err(col, &format!("invalid number '{text}'")))?;
+            toks.push((Tok::Int(value), col));
+        } else if ch.is_alphabetic() || ch == '_' || ch == '.' {
+            let start = idx;
+            while idx < chars.len() && (chars[idx].is_alphanumeric() || chars[idx] == '_' || chars[idx] == '.') {
+                idx += 1;
+            }
+            toks.push((Tok::Ident(chars[start..idx].iter().collect()), col));
+        } else if ch == '"' {
+            idx += 1;
+            let mut text = String::new();
+            loop {
+                match chars.get(idx) {
+                    None => return Err(err(col, "unterminated string")),
+                    Some('"') => break,
+                    Some(_) => text.push(escaped(&mut idx).ok_or_else(|| err(col, "unterminated string"))?),
+                }
+            }
+            idx += 1;
+            toks.push((Tok::Str(text), col));
+        } else if ch == '\'' {
+            idx += 1;
+            let value = escaped(&mut idx).ok_or_else(|| err(col, "unterminated character"))?;
+            if chars.get(idx) != Some(&'\'') {
+                return Err(err(col, "unterminated character"));
+            }
+            idx += 1;
+            toks.push((Tok::Int(value as Word), col));
+        } else if ":,[]#+-=".contains(ch) {
+            idx += 1;
+            toks.push((Tok::Punct(ch), col));
+        } else {
+            return Err(err(col, &format!("unexpected character '{ch}'")));
+        }
+    }
+
+    Ok(toks)
+}
+
+// Assembles source text into a program
+pub fn assemble(src: &str) -> Result<Program, AsmError> {
+    let mut words: Vec<(usize, Cell)> = Vec::new();
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut locals: HashMap<String, Local> = HashMap::new();
+    let mut disp = Some(0); // Relative base displacement, None once unknown
+
+    for (idx, text) in src.lines().enumerate() {
+        let mut line = Line { no: idx + 1, toks: lex(idx + 1, text)?, pos: 0, src: text };
+
+        if line.toks.get(1).map(|(tok, _)| tok) == Some(&Tok::Punct(':')) {
+            let (name, col) = line.ident()?;
+            line.pos += 1;
+            if labels.insert(name.clone(), words.len()).is_some() {
+                return line.err(col, format!("duplicate label '{name}'"));
+            }
+        }
+        if line.at_end() {
+            continue;
+        }
+
+        let (head, col) = line.ident()?;
+        match head.to_ascii_lowercase().as_str() {
+            "data" => {
+                for expr in line.list(|line| line.expr())? {
+                    words.push((line.no, Cell::Expr(expr)));
+                }
+            }
+            "string" => match line.next() {
+                Some((Tok::Str(text), _)) => {
+                    words.extend(text.chars().map(|ch| (line.no, Cell::Fixed(ch as Word))));
+                }
+                _ => return line.err(col, "expected a string"),
+            },
+            "local" => {
+                let (name, col) = line.ident()?;
+                line.expect('=')?;
+                let slot = line.expr()?;
+                let Some(slot) = slot.constant(line.no)? else {
+                    return line.err(col, "local offset must be a number");
+                };
+                let Some(disp) = disp else {
+                    return line.err(col, "relative base is unknown after a non-immediate RBX");
+                };
+                locals.insert(name, Local { slot, disp });
+            }
+            mnemonic => {
+                let Some(info) = OpInfo::by_mnemonic(mnemonic) else {
+                    return line.err(col, format!("unknown mnemonic '{head}'"));
+                };
+                let operands = line.list(|line| operand(line, &locals, disp))?;
+                if operands.len() != info.arity {
+                    return line.err(col, format!("{} takes {} operands, found {}", info.mnemonic, info.arity, operands.len()));
+                }
+
+                let mut word = info.opcode;
+                let mut scale = 100;
+                for (pos, (mode, _, col)) in operands.iter().enumerate() {
+                    if *mode == 1 && info.dest == Some(pos + 1) {
+                        return line.err(*col, format!("{} cannot write to an immediate", info.mnemonic));
+                    }
+                    word += mode * scale;
+                    scale *= 10;
+                }
+                if info.opcode == 9 {
+                    disp = match operands[0] {
+                        (1, ref expr, _) => disp.zip(expr.constant(line.no)?).and_then(|(d, n)| d.checked_add(n)),
+                        _ => None,
+                    };
+                }
+
+                words.push((line.no, Cell::Fixed(word)));
+                words.extend(operands.into_iter().map(|(_, expr, _)| (line.no, Cell::Expr(expr))));
+            }
+        }
+
+        if !line.at_end() {
+            return line.err(line.col(), "unexpected trailing input");
+        }
+    }
+
+    words
+        .into_iter()
+        .map(|(line, word)| match word {
+            Cell::Fixed(value) => Ok(value),
+            Cell::Expr(expr) => expr.eval(&labels, line),
+        })
+        .collect()
+}
+
+// Parses an operand into its mode, value and column
+fn operand(line: &mut Line, locals: &HashMap<String, Local>, disp: Option<Word>) -> Result<(Word, Expr, usize), AsmError> {
+    let col = line.col();
+    if line.eat('[') {
+        let expr = line.expr()?;
+        line.expect(']')?;
+        return Ok((0, expr, col));
+    }
+    if line.eat('#') {
+        return Ok((1, line.expr()?, col));
+    }
+
+    let (name, _) = line.ident()?;
+    let base = if name.eq_ignore_ascii_case("rb") {
+        0
+    } else if let Some(local) = locals.get(&name) {
+        match disp {
+            Some(disp) => match local.slot.checked_add(local.disp).and_then(|addr| addr.checked_sub(disp)) {
+                Some(offset) => offset,
+                None => return line.err(col, format!("offset of '{name}' overflows a word")),
+            },
+            None => return line.err(col, "relative base is unknown after a non-immediate RBX"),
+        }
+    } else {
+        return line.err(col, format!("'{name}' needs a mode prefix: [x], #x or rb+x"));
+    };
+
+    let expr = if line.eat('+') || line.peek() == Some(&Tok::Punct('-')) {
+        line.expr()?
+    } else {
+        Expr { terms: Vec::new() }
+    };
+    let mut terms = vec![(1, Atom::Num(base), col)];
+    terms.extend(expr.terms);
+
+    Ok((2, Expr { terms }, col))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_day7_example() {
+        let src = "
+                IN  [a]
+                IN  [b]
+                MUL [b], #10, [b]
+                ADD [b], [a], [a]
+                OUT [a]
+                HLT
+            a:  data 0
+            b:  data 0
+        ";
+        assert_eq!(assemble(src).unwrap(), vec![3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0]);
+    }
+
+    #[test]
+    fn labels_strings_and_relative() {
+        let src = "
+                RBX #buf
+            loop:
+                OUT rb+0
+                RBX #1
+                JNZ rb+0, #loop
+                HLT
+            buf: string \"Hi\\n\"
+                data 0
+        ";
+        assert_eq!(assemble(src).unwrap(), vec![109,10,204,0,109,1,1205,0,2,99,72,105,10,0]);
+    }
+
+    #[test]
+    fn locals_follow_relative_base() {
+        let src = "
+                local x = 0
+                ADD #1, #2, x
+                RBX #3
+                OUT x
+                HLT
+        ";
+        assert_eq!(assemble(src).unwrap(), vec![21101,1,2,0,109,3,204,-3,99]);
+    }
+
+    #[test]
+    fn errors_carry_line_and_column() {
+        assert_eq!(assemble("  ADD [1], #2\n").unwrap_err(), AsmError { line: 1, col: 3, msg: "ADD takes 3 operands, found 2".to_string() });
+        assert_eq!(assemble("HLT\n  OUT [nowhere]").unwrap_err().col, 8);
+        assert_eq!(assemble("IN #4").unwrap_err(), AsmError { line: 1, col: 4, msg: "IN cannot write to an immediate".to_string() });
+
+        let max = Word::MAX.to_string();
+        let overflow = |col| AsmError { line: 2, col, msg: "expression overflows a word".to_string() };
+        assert_eq!(assemble(&format!("HLT\n  data 1, {max} + 1")).unwrap_err(), overflow(max.len() + 14));
+        assert_eq!(assemble(&format!("HLT\nlocal x = -{max} - 2")).unwrap_err(), overflow(max.len() + 15));
+    }
+}
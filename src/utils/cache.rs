@@ -0,0 +1,91 @@
+use super::intcode::Word;
+use super::memory::Memory;
+
+// Highest address whose decoding is cached, so a jump far into paged memory
+// does not allocate a huge cache
+const MAX_ENTRIES: usize = 1 << 16;
+
+// An instruction word with its opcode and parameter modes
+#[derive(Debug, Clone, Copy, Default)]
+struct Decoded {
+    word: Word,
+    opcode: i8,
+    modes: [i8; 3],
+    valid: bool, // Cleared when the word is written
+}
+
+impl Decoded {
+    fn expand(&self) -> (Word, Word, [Word; 3]) {
+        (self.word, self.opcode as Word, self.modes.map(Word::from))
+    }
+
+    fn new(word: Word) -> Self {
+        let mut code = word / 100;
+        let mut modes = [0; 3];
+        for mode in modes.iter_mut() {
+            *mode = (code % 10) as i8;
+            code /= 10;
+        }
+        Self { word, opcode: (word % 100) as i8, modes, valid: true }
+    }
+}
+
+// Decoded instruction words, cached per address. A hit skips both the memory
+// read and the decoding. The machine invalidates an entry whenever it writes
+// the address, by self-modifying code or the host, so hits are not checked
+// against memory. The cache outlives reboots, keeping the entries the new
+// program leaves unchanged.
+#[derive(Debug, Clone)]
+pub(crate) struct DecodeCache {
+    enabled: bool,
+    entries: Vec<Decoded>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self { enabled: true, entries: Vec::new() }
+    }
+}
+
+impl DecodeCache {
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.clear();
+    }
+
+    // Word, opcode and modes of the instruction at an address
+    pub(crate) fn fetch(&mut self, addr: usize, mem: &Memory) -> (Word, Word, [Word; 3]) {
+        if let Some(entry) = self.entries.get(addr)
+            && entry.valid
+        {
+            return entry.expand();
+        }
+
+        let entry = Decoded::new(mem.get(addr));
+        if self.enabled && addr < MAX_ENTRIES {
+            if addr >= self.entries.len() {
+                self.entries.resize(addr + 1, Decoded::default());
+            }
+            self.entries[addr] = entry;
+        }
+        entry.expand()
+    }
+
+    // Forgets the decoding of a written address
+    pub(crate) fn invalidate(&mut self, addr: usize) {
+        if let Some(entry) = self.entries.get_mut(addr) {
+            entry.valid = false;
+        }
+    }
+
+    // Keeps only the entries that still match the words of a newly loaded program
+    pub(crate) fn reload(&mut self, prog: &[Word]) {
+        for (addr, entry) in self.entries.iter_mut().enumerate() {
+            entry.valid &= prog.get(addr).copied().unwrap_or(0) == entry.word;
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries = Vec::new();
+    }
+}
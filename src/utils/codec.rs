@@ -0,0 +1,80 @@
+use super::intcode::Word;
+
+// Zigzag LEB128 varints shared by the binary trace and snapshot formats
+
+// Ran out of bytes while decoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Truncated;
+
+// Appends a zigzag LEB128 varint
+pub(crate) fn put_varint(buf: &mut Vec<u8>, value: i128) {
+    let mut zz = ((value << 1) ^ (value >> 127)) as u128;
+    loop {
+        let byte = (zz & 0x7f) as u8;
+        zz >>= 7;
+        if zz == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+// Cursor over binary data that yields bytes and varints
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], pos: usize) -> Self {
+        Self { bytes, pos }
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, Truncated> {
+        let byte = *self.bytes.get(self.pos).ok_or(Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    // Reads a zigzag LEB128 varint
+    pub(crate) fn varint(&mut self) -> Result<i128, Truncated> {
+        let mut zz: u128 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= 128 {
+                return Err(Truncated);
+            }
+            zz |= ((byte & 0x7f) as u128) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok((zz >> 1) as i128 ^ -((zz & 1) as i128));
+            }
+        }
+    }
+
+    // Reads a varint length prefixed list of varints
+    pub(crate) fn list(&mut self) -> Result<Vec<i128>, Truncated> {
+        let len = self.varint()?;
+        (0..len).map(|_| self.varint()).collect()
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+}
+
+// Appends a varint length prefixed list of varints
+pub(crate) fn put_list(buf: &mut Vec<u8>, values: impl ExactSizeIterator<Item = i128>) {
+    put_varint(buf, values.len() as i128);
+    for value in values {
+        put_varint(buf, value);
+    }
+}
+
+// A word as a varint value, whichever width words have
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn widen(value: Word) -> i128 {
+    value as i128
+}
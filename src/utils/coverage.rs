@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, fmt::Write};
+use super::disasm::disassemble;
+use super::intcode::{OpInfo, Step, Word};
+use super::memory::PAGE;
+
+const EXEC: u8 = 1;  // An instruction started here
+const READ: u8 = 2;  // Read by an operand
+const WRITE: u8 = 4; // Written by an instruction
+
+// Which addresses ran as instructions, were read as data or written, over
+// one or more runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pages: BTreeMap<usize, Box<[u8; PAGE]>>, // Flags per address, in pages allocated when first marked
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Whether an instruction starting at an address was executed
+    pub fn executed(&self, addr: usize) -> bool {
+        self.flags(addr) & EXEC != 0
+    }
+
+    pub fn read(&self, addr: usize) -> bool {
+        self.flags(addr) & READ != 0
+    }
+
+    pub fn written(&self, addr: usize) -> bool {
+        self.flags(addr) & WRITE != 0
+    }
+
+    // Adds the coverage of another run
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&idx, flags) in &other.pages {
+            let page = self.pages.entry(idx).or_insert_with(|| Box::new([0; PAGE]));
+            page.iter_mut().zip(flags.iter()).for_each(|(cell, flags)| *cell |= flags);
+        }
+    }
+
+    // Instructions of the program's disassembly that were executed, and how many it has
+    pub fn instructions(&self, prog: &[Word]) -> (usize, usize) {
+        let dis = disassemble(prog);
+        let addrs: Vec<usize> = dis.instructions().map(|inst| inst.addr).collect();
+        (addrs.iter().filter(|&&addr| self.executed(addr)).count(), addrs.len())
+    }
+
+    // Disassembly of the program with each line marked x if it was executed,
+    // r if any of its cells was read and w if any was written
+    pub fn render(&self, prog: &[Word]) -> String {
+        let dis = disassemble(prog);
+        let (covered, total) = self.instructions(prog);
+        let pct = covered as f64 * 100.0 / total.max(1) as f64;
+        let mut out = format!("covered {covered} of {total} instructions ({pct:.1}%)\n");
+        for region in &dis.regions {
+            if let Some(label) = dis.labels.get(&region.addr()) {
+                writeln!(out, "    {label}:").unwrap();
+            }
+            let flags = (region.addr()..region.addr() + region.len()).fold(0, |flags, addr| flags | self.flags(addr));
+            let marks: String = [(EXEC, 'x'), (READ, 'r'), (WRITE, 'w')]
+                .iter()
+                .map(|&(flag, mark)| if flags & flag != 0 { mark } else { '-' })
+                .collect();
+            writeln!(out, "{marks} {}", dis.line(region)).unwrap();
+        }
+
+        out
+    }
+
+    fn flags(&self, addr: usize) -> u8 {
+        self.pages.get(&(addr / PAGE)).map_or(0, |page| page[addr % PAGE])
+    }
+
+    fn mark(&mut self, addr: usize, flag: u8) {
+        self.pages.entry(addr / PAGE).or_insert_with(|| Box::new([0; PAGE]))[addr % PAGE] |= flag;
+    }
+
+    // Marks what an executed instruction touched
+    pub(crate) fn record(&mut self, step: &Step) {
+        self.mark(step.ip, EXEC);
+        let dest = OpInfo::lookup(step.opcode).and_then(|info| info.dest);
+        for (idx, operand) in step.operands().iter().enumerate() {
+            if let Some(addr) = operand.addr
+                && dest != Some(idx + 1)
+            {
+                self.mark(addr, READ);
+            }
+        }
+        if let Some((addr, _)) = step.write {
+            self.mark(addr, WRITE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Machine, MachineState, Memory};
+
+    #[test]
+    fn merged_runs_and_listing() {
+        // Outputs 0 if the input is 0, 1 otherwise
+        let prog = vec![3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9];
+        let mut total = Coverage::new();
+        for (input, covered) in [(0, 4), (5, 5)] {
+            let mut vm = Machine::new(&prog);
+            vm.start_coverage();
+            vm.input_ext(&[input]);
+            assert_eq!(vm.run(), Ok(MachineState::Halted));
+            assert_eq!(vm.dump_output(), &vec![(input != 0) as Word]);
+
+            let cov = vm.stop_coverage().unwrap();
+            assert_eq!(cov.instructions(&prog), (covered, 5));
+            assert_eq!(cov.executed(5), input != 0);
+            total.merge(&cov);
+        }
+
+        assert!(total.written(12) && total.written(13) && !total.written(14));
+        assert!(total.read(12) && total.read(15) && !total.read(0));
+        let lines: Vec<String> = total.render(&prog).lines().map(|l| l.trim_end().to_string()).collect();
+        assert_eq!(lines, vec![
+            "covered 5 of 5 instructions (100.0%)",
+            "x-- 0000  3,12                     IN  [12]",
+            "x-- 0002  6,12,15                  JZ  [12], [15]                ; indirect jump",
+            "x-- 0005  1,13,14,13               ADD [13], [14], [13]",
+            "x-- 0009  4,13                     OUT [13]",
+            "x-- 0011  99                       HLT",
+            "-rw 0012  -1,0,1,9                 DATA -1, 0, 1, 9",
+        ]);
+
+        // A write far out in paged memory marks one page of flags
+        let mut vm = Machine::with_memory(Memory::paged(&[1101,1,1,5_000_000_000,99]));
+        vm.start_coverage();
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        let cov = vm.stop_coverage().unwrap();
+        assert!(cov.written(5_000_000_000) && !cov.written(4_999_999_999));
+        assert_eq!(cov.pages.len(), 2);
+    }
+}
@@ -0,0 +1,872 @@
+use std::collections::{BTreeMap, BTreeSet};
+use super::disasm::Inst;
+use super::flow::{Block, Cfg, Edge, EdgeKind};
+use super::intcode::Word;
+
+// Virtual node every returning block leads to, for post-dominators
+const EXIT: usize = usize::MAX;
+
+// An operand as the decompiler names it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Opnd {
+    Imm(Word),
+    Cell(Word), // Memory cell, a global variable
+    Slot(Word), // Relative base slot, counted from the relative base on entry to the function
+    Rel(Word),  // Relative base slot where the relative base is not known statically
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Val(Opnd),
+    Neg(Opnd),
+    Bin(Opnd, &'static str, Opnd),
+    Input,
+}
+
+// A comparison deciding a branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cond {
+    lhs: Opnd,
+    op: &'static str,
+    rhs: Opnd,
+}
+
+impl Cond {
+    fn negate(self) -> Self {
+        let op = match self.op {
+            "==" => "!=",
+            "!=" => "==",
+            "<" => ">=",
+            _ => "<",
+        };
+        Cond { op, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stmt {
+    Assign(Opnd, Expr),
+    Output(Opnd),
+    Call(usize, Vec<Expr>),
+    GotoIf(Cond, Opnd), // Conditional jump to a target unknown statically
+    MoveBase(Opnd),     // Non-immediate RBX
+}
+
+// How control leaves a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Exit {
+    Next(Option<usize>), // Continues with a block, None when it runs into data
+    Branch { cond: Cond, taken: usize, next: Option<usize> },
+    Return,
+    Halt,
+    Indirect(Opnd),
+}
+
+// A call by the calling convention: the return address is stored in a
+// relative base slot, then an unconditional jump goes to the function
+#[derive(Debug, Clone, Copy)]
+struct Call {
+    target: usize,
+    slot: Option<Word>, // Slot of the return address, counted from the caller's entry
+}
+
+// A function and the code reachable in it without following calls
+#[derive(Debug)]
+struct Function {
+    entry: usize,
+    cfg: Cfg,
+    deltas: BTreeMap<usize, Option<Word>>, // Relative base before each instruction, counted from entry
+    calls: BTreeMap<usize, Call>,          // Calls by the address of their jump
+    stores: BTreeSet<usize>,               // Instructions storing a return address
+}
+
+// Writes of an immediate value into a relative base slot, e.g. `ADD #ret, #0, rb+0`
+fn stored_const(inst: &Inst) -> Option<(Word, Word)> {
+    if inst.modes[0] != 1 || inst.modes[1] != 1 || inst.modes[2] != 2 {
+        return None;
+    }
+    let value = match inst.info.opcode {
+        1 => inst.params[0].checked_add(inst.params[1])?,
+        2 => inst.params[0].checked_mul(inst.params[1])?,
+        _ => return None,
+    };
+    Some((value, inst.params[2]))
+}
+
+impl Function {
+    // Follows the code of a function from its entry. Calls continue at their
+    // return address, jumps through a relative base slot are returns.
+    fn explore(prog: &[Word], entry: usize) -> Self {
+        let mut insts: BTreeMap<usize, Inst> = BTreeMap::new();
+        let mut deltas = BTreeMap::new();
+        let mut calls = BTreeMap::new();
+        let mut stores = BTreeSet::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut queue: Vec<(usize, Option<Word>)> = vec![(entry, Some(0))];
+
+        while let Some((start, mut delta)) = queue.pop() {
+            let mut addr = start;
+            let mut run: Vec<Inst> = Vec::new();
+            loop {
+                if insts.contains_key(&addr) {
+                    if deltas[&addr] != delta {
+                        deltas.insert(addr, None);
+                    }
+                    break;
+                }
+                let overlaps = insts.range(..addr).next_back().is_some_and(|(_, inst)| inst.addr + inst.size() > addr);
+                let Some(inst) = Inst::decode(prog, addr).filter(|_| !overlaps) else {
+                    break;
+                };
+                insts.insert(addr, inst);
+                deltas.insert(addr, delta);
+                let next = addr + inst.size();
+
+                match inst.info.opcode {
+                    9 if inst.modes[0] == 1 => delta = delta.and_then(|d| d.checked_add(inst.params[0])),
+                    9 => delta = None,
+                    99 => {
+                        leaders.insert(next);
+                        break;
+                    }
+                    _ => {}
+                }
+                if inst.is_jump() && !inst.never_jumps() {
+                    let store = run.iter().rev().take_while(|inst| !inst.is_jump()).find(|prev| {
+                        stored_const(prev).is_some_and(|(value, _)| usize::try_from(value) == Ok(next))
+                    });
+                    match (inst.jump_target(), store) {
+                        (Some(target), Some(store)) if inst.always_jumps() => {
+                            let slot = deltas[&store.addr].and_then(|d: Word| d.checked_add(stored_const(store)?.1));
+                            calls.insert(addr, Call { target, slot });
+                            stores.insert(store.addr);
+                        }
+                        (Some(target), _) => {
+                            leaders.insert(next);
+                            leaders.insert(target);
+                            queue.push((target, delta));
+                            if inst.always_jumps() {
+                                break;
+                            }
+                        }
+                        (None, _) => {
+                            leaders.insert(next);
+                            if inst.always_jumps() {
+                                break;
+                            }
+                        }
+                    }
+                }
+                run.push(inst);
+                addr = next;
+            }
+        }
+
+        let mut blocks: BTreeMap<usize, Block> = BTreeMap::new();
+        let mut current: Vec<Inst> = Vec::new();
+        for inst in insts.into_values() {
+            let contiguous = current.last().is_some_and(|last| last.addr + last.size() == inst.addr);
+            if !current.is_empty() && (leaders.contains(&inst.addr) || !contiguous) {
+                let block = Block { insts: std::mem::take(&mut current), indirect: false };
+                blocks.insert(block.start(), block);
+            }
+            current.push(inst);
+        }
+        if !current.is_empty() {
+            let block = Block { insts: current, indirect: false };
+            blocks.insert(block.start(), block);
+        }
+
+        let mut edges = Vec::new();
+        for (&start, block) in &mut blocks {
+            let last = *block.last();
+            let call = calls.contains_key(&last.addr);
+            if last.is_jump() && !last.never_jumps() && !call {
+                match last.jump_target() {
+                    Some(to) => edges.push(Edge { from: start, to, kind: EdgeKind::Taken }),
+                    None => block.indirect = true,
+                }
+            }
+            if call || last.falls_through() {
+                edges.push(Edge { from: start, to: block.end(), kind: EdgeKind::FallThrough });
+            }
+        }
+        edges.retain(|edge| blocks.contains_key(&edge.to));
+
+        Function { entry, cfg: Cfg { blocks, edges }, deltas, calls, stores }
+    }
+
+    // An operand of an instruction, with relative base slots counted from entry
+    fn opnd(&self, inst: &Inst, idx: usize) -> Opnd {
+        let raw = inst.params[idx];
+        match inst.modes[idx] {
+            0 => Opnd::Cell(raw),
+            1 => Opnd::Imm(raw),
+            _ => match self.deltas[&inst.addr].and_then(|d| d.checked_add(raw)) {
+                Some(slot) => Opnd::Slot(slot),
+                None => Opnd::Rel(raw),
+            },
+        }
+    }
+
+    // Operands an instruction reads
+    fn reads(&self, inst: &Inst) -> impl Iterator<Item = Opnd> + '_ {
+        let dest = inst.info.dest;
+        let inst = *inst;
+        (0..inst.info.arity).filter(move |&idx| dest != Some(idx + 1)).map(move |idx| self.opnd(&inst, idx))
+    }
+}
+
+// Function names and parameters shared while rendering
+struct Names<'a> {
+    funcs: &'a BTreeMap<usize, Function>,
+    params: BTreeMap<usize, (Word, usize)>, // Return address slot and parameter count by entry
+    reads: BTreeMap<Opnd, usize>,           // Static reads of each memory cell
+}
+
+impl Names<'_> {
+    fn func_name(&self, entry: usize) -> String {
+        if entry == 0 { "main".to_string() } else { format!("func_{entry:04}") }
+    }
+
+    fn name(&self, func: &Function, opnd: Opnd) -> String {
+        match opnd {
+            Opnd::Imm(value) => value.to_string(),
+            Opnd::Cell(addr) if addr < 0 => format!("var_m{}", addr.unsigned_abs()),
+            Opnd::Cell(addr) => format!("var{addr}"),
+            Opnd::Slot(slot) => match self.params.get(&func.entry) {
+                Some(&(ret, count)) if slot > ret && slot - ret <= count as Word => format!("p{}", slot - ret),
+                _ if slot < 0 => format!("outer{}", -slot),
+                _ => format!("local{slot}"),
+            },
+            Opnd::Rel(offset) => format!("rb[{offset}]"),
+        }
+    }
+
+    fn expr(&self, func: &Function, expr: &Expr) -> String {
+        match expr {
+            Expr::Val(opnd) => self.name(func, *opnd),
+            Expr::Neg(opnd) => format!("-{}", self.name(func, *opnd)),
+            Expr::Bin(lhs, op, rhs) => format!("{} {op} {}", self.name(func, *lhs), self.name(func, *rhs)),
+            Expr::Input => "input()".to_string(),
+        }
+    }
+
+    fn cond(&self, func: &Function, cond: Cond) -> String {
+        format!("{} {} {}", self.name(func, cond.lhs), cond.op, self.name(func, cond.rhs))
+    }
+
+    fn stmt(&self, func: &Function, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Assign(dest, expr) => format!("{} = {};", self.name(func, *dest), self.expr(func, expr)),
+            Stmt::Output(opnd) => format!("output({});", self.name(func, *opnd)),
+            Stmt::Call(target, args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.expr(func, arg)).collect();
+                format!("{}({});", self.func_name(*target), args.join(", "))
+            }
+            Stmt::GotoIf(cond, target) => format!("if ({}) goto *{};", self.cond(func, *cond), self.name(func, *target)),
+            Stmt::MoveBase(opnd) => format!("rb += {};", self.name(func, *opnd)),
+        }
+    }
+}
+
+// Lowers the instructions of a block to statements and its exit. Calls take
+// the arguments stored in the slots after the return address just before.
+fn lower(func: &Function, block: &Block, reads: &BTreeMap<Opnd, usize>) -> (Vec<Stmt>, Exit) {
+    let mut stmts = Vec::new();
+    let next_block = |addr: usize| func.cfg.blocks.contains_key(&addr).then_some(addr);
+    let mut exit = Exit::Next(next_block(block.end()));
+
+    for inst in &block.insts {
+        let op = |idx| func.opnd(inst, idx);
+        match inst.info.opcode {
+            _ if func.stores.contains(&inst.addr) => {}
+            1 => stmts.push(Stmt::Assign(op(2), match (op(0), op(1)) {
+                (Opnd::Imm(0), val) | (val, Opnd::Imm(0)) => Expr::Val(val),
+                (lhs, Opnd::Imm(rhs)) if rhs < 0 => Expr::Bin(lhs, "-", Opnd::Imm(rhs.wrapping_neg())),
+                (lhs, rhs) => Expr::Bin(lhs, "+", rhs),
+            })),
+            2 => stmts.push(Stmt::Assign(op(2), match (op(0), op(1)) {
+                (Opnd::Imm(1), val) | (val, Opnd::Imm(1)) => Expr::Val(val),
+                (Opnd::Imm(-1), val) | (val, Opnd::Imm(-1)) => Expr::Neg(val),
+                (lhs, rhs) => Expr::Bin(lhs, "*", rhs),
+            })),
+            3 => stmts.push(Stmt::Assign(op(0), Expr::Input)),
+            4 => stmts.push(Stmt::Output(op(0))),
+            7 => stmts.push(Stmt::Assign(op(2), Expr::Bin(op(0), "<", op(1)))),
+            8 => stmts.push(Stmt::Assign(op(2), Expr::Bin(op(0), "==", op(1)))),
+            9 if inst.modes[0] == 1 => {}
+            9 => stmts.push(Stmt::MoveBase(op(0))),
+            99 => exit = Exit::Halt,
+            _ if inst.never_jumps() => {}
+            _ => {
+                if let Some(call) = func.calls.get(&inst.addr) {
+                    let args = call.slot.map_or(Vec::new(), |slot| take_args(&mut stmts, slot));
+                    stmts.push(Stmt::Call(call.target, args));
+                    continue;
+                }
+
+                let mut cond = Cond { lhs: op(0), op: "!=", rhs: Opnd::Imm(0) };
+                if let Some(Stmt::Assign(dest, Expr::Bin(lhs, cmp @ ("<" | "=="), rhs))) = stmts.last()
+                    && *dest == cond.lhs
+                    && is_temp(func, *dest, reads)
+                {
+                    cond = Cond { lhs: *lhs, op: cmp, rhs: *rhs };
+                    stmts.pop();
+                }
+                if inst.info.opcode == 6 {
+                    cond = cond.negate();
+                }
+
+                exit = match inst.jump_target().and_then(next_block) {
+                    Some(target) if inst.always_jumps() => Exit::Next(Some(target)),
+                    Some(taken) => Exit::Branch { cond, taken, next: next_block(block.end()) },
+                    None if inst.always_jumps() && inst.modes[1] == 2 => Exit::Return,
+                    None if inst.always_jumps() => Exit::Indirect(op(1)),
+                    None => {
+                        stmts.push(Stmt::GotoIf(cond, op(1)));
+                        Exit::Next(next_block(block.end()))
+                    }
+                };
+            }
+        }
+    }
+
+    (stmts, exit)
+}
+
+// Takes the trailing assignments to the slots after a return address, if
+// they fill the slots from the first one on
+fn take_args(stmts: &mut Vec<Stmt>, ret: Word) -> Vec<Expr> {
+    let mut args = BTreeMap::new();
+    for stmt in stmts.iter().rev() {
+        match stmt {
+            Stmt::Assign(Opnd::Slot(slot), expr) if *slot > ret && !args.contains_key(slot) => {
+                args.insert(*slot, expr.clone());
+            }
+            _ => break,
+        }
+    }
+    if args.keys().copied().ne(ret + 1..=ret + args.len() as Word) {
+        return Vec::new();
+    }
+
+    stmts.truncate(stmts.len() - args.len());
+    args.into_values().collect()
+}
+
+// Whether a value is read only once, by the branch testing it, so the
+// assignment can fold into the condition
+fn is_temp(func: &Function, opnd: Opnd, reads: &BTreeMap<Opnd, usize>) -> bool {
+    match opnd {
+        Opnd::Cell(_) => reads.get(&opnd) == Some(&1),
+        Opnd::Slot(_) => {
+            let insts = func.cfg.blocks.values().flat_map(|block| &block.insts);
+            insts.flat_map(|inst| func.reads(inst)).filter(|&read| read == opnd).count() == 1
+        }
+        _ => false,
+    }
+}
+
+// Dominator sets of a graph given by predecessors, from one root
+fn dominators(nodes: &BTreeSet<usize>, root: usize, preds: &BTreeMap<usize, Vec<usize>>) -> BTreeMap<usize, BTreeSet<usize>> {
+    let mut dom: BTreeMap<usize, BTreeSet<usize>> = nodes.iter().map(|&node| (node, nodes.clone())).collect();
+    dom.insert(root, BTreeSet::from([root]));
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in nodes.iter().filter(|&&node| node != root) {
+            let mut set = preds
+                .get(&node)
+                .into_iter()
+                .flatten()
+                .map(|pred| &dom[pred])
+                .fold(None, |acc: Option<BTreeSet<usize>>, set| {
+                    Some(acc.map_or(set.clone(), |acc| &acc & set))
+                })
+                .unwrap_or_default();
+            set.insert(node);
+            if set != dom[&node] {
+                dom.insert(node, set);
+                changed = true;
+            }
+        }
+    }
+
+    dom
+}
+
+// The closest strict dominator, the one with the most dominators of its own
+fn immediate(dom: &BTreeMap<usize, BTreeSet<usize>>, node: usize) -> Option<usize> {
+    dom[&node].iter().filter(|&&other| other != node && other != EXIT).max_by_key(|other| dom[other].len()).copied()
+}
+
+// A loop being written out
+struct Loop {
+    header: usize,
+    follow: Option<usize>, // Where the loop exits to
+    indent: usize,         // Indent of the loop body
+    latch: Option<Cond>,   // Condition of a do-while
+    continues: usize,      // Continue statements written
+}
+
+enum Line {
+    Text(usize, String),
+    Label(usize, usize), // Indent and address, kept if some goto needs it
+}
+
+// Writes one function as structured code
+struct Writer<'a> {
+    prog: &'a Names<'a>,
+    func: &'a Function,
+    body: BTreeMap<usize, (Vec<Stmt>, Exit)>,
+    pdom: BTreeMap<usize, BTreeSet<usize>>,
+    follows: BTreeMap<usize, Option<usize>>, // Loop headers and where their loops exit to
+    lines: Vec<Line>,
+    indent: usize,
+    emitted: BTreeSet<usize>,
+    loops: Vec<Loop>,
+    gotos: BTreeSet<usize>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(prog: &'a Names<'a>, func: &'a Function) -> Self {
+        let body: BTreeMap<usize, (Vec<Stmt>, Exit)> =
+            func.cfg.blocks.iter().map(|(&start, block)| (start, lower(func, block, &prog.reads))).collect();
+        let succs: BTreeMap<usize, Vec<usize>> = body
+            .iter()
+            .map(|(&start, (_, exit))| {
+                let succs = match *exit {
+                    Exit::Next(Some(next)) => vec![next],
+                    Exit::Branch { taken, next, .. } => [Some(taken), next].into_iter().flatten().collect(),
+                    _ => Vec::new(),
+                };
+                (start, succs)
+            })
+            .collect();
+        let mut preds: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut rev: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (&from, tos) in &succs {
+            for &to in tos {
+                preds.entry(to).or_default().push(from);
+                rev.entry(from).or_default().push(to);
+            }
+            if tos.is_empty() {
+                rev.entry(from).or_default().push(EXIT);
+            }
+        }
+
+        let nodes: BTreeSet<usize> = body.keys().copied().collect();
+        let dom = dominators(&nodes, func.entry, &preds);
+        let mut follows = BTreeMap::new();
+        for &header in &nodes {
+            let latches: Vec<usize> = preds.get(&header).into_iter().flatten().copied().filter(|pred| dom[pred].contains(&header)).collect();
+            if latches.is_empty() {
+                continue;
+            }
+            // Going round again counts as leaving, so an endless loop still has merge points
+            latches.iter().for_each(|&latch| rev.entry(latch).or_default().push(EXIT));
+            let mut inside = BTreeSet::from([header]);
+            let mut stack = latches;
+            while let Some(node) = stack.pop() {
+                if inside.insert(node) {
+                    stack.extend(preds.get(&node).into_iter().flatten());
+                }
+            }
+            let follow = inside.iter().flat_map(|node| &succs[node]).copied().filter(|to| !inside.contains(to)).min();
+            follows.insert(header, follow);
+        }
+
+        // Blocks that never reach an exit only post-dominate themselves
+        let mut reaching = BTreeSet::from([EXIT]);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (&node, tos) in &rev {
+                if !reaching.contains(&node) && tos.iter().any(|to| reaching.contains(to)) {
+                    reaching.insert(node);
+                    changed = true;
+                }
+            }
+        }
+        let rev: BTreeMap<usize, Vec<usize>> = rev
+            .into_iter()
+            .filter(|(node, _)| reaching.contains(node))
+            .map(|(node, tos)| (node, tos.into_iter().filter(|to| reaching.contains(to)).collect()))
+            .collect();
+        let mut pdom = dominators(&(&nodes & &reaching), EXIT, &rev);
+        for node in nodes.iter().filter(|node| !reaching.contains(node)) {
+            pdom.insert(*node, BTreeSet::from([*node]));
+        }
+
+        Self {
+            prog,
+            func,
+            body,
+            pdom,
+            follows,
+            lines: Vec::new(),
+            indent: 1,
+            emitted: BTreeSet::new(),
+            loops: Vec::new(),
+            gotos: BTreeSet::new(),
+        }
+    }
+
+    fn line(&mut self, text: String) {
+        self.lines.push(Line::Text(self.indent, text));
+    }
+
+    fn goto(&mut self, block: usize) {
+        self.gotos.insert(block);
+        self.line(format!("goto L{block:04};"));
+    }
+
+    fn cond(&self, cond: Cond) -> String {
+        self.prog.cond(self.func, cond)
+    }
+
+    // Writes the function, declaration included
+    fn write(mut self) -> String {
+        self.seq(Some(self.func.entry), None);
+        // Blocks only reached by gotos
+        while let Some(block) = self.gotos.difference(&self.emitted).next().copied() {
+            self.seq(Some(block), None);
+        }
+
+        let func = self.func;
+        let params = self.prog.params.get(&func.entry).map_or(0, |&(_, count)| count);
+        let params: Vec<String> = (1..=params).map(|idx| format!("p{idx}")).collect();
+        let mut out = format!("void {}({}) {{\n", self.prog.func_name(func.entry), params.join(", "));
+        for line in &self.lines {
+            match line {
+                Line::Text(indent, text) => out += &format!("{}{text}\n", "    ".repeat(*indent)),
+                Line::Label(indent, addr) if self.gotos.contains(addr) => {
+                    out += &format!("{}L{addr:04}:\n", "    ".repeat(indent - 1));
+                }
+                Line::Label(..) => {}
+            }
+        }
+        out.push_str("}\n");
+
+        out
+    }
+
+    // Writes blocks from `cur` on until control reaches `stop`
+    fn seq(&mut self, mut cur: Option<usize>, stop: Option<usize>) {
+        while let Some(block) = cur {
+            if cur == stop {
+                return;
+            }
+            if let Some((idx, lp)) = self.loops.iter().enumerate().rev().find(|(_, lp)| lp.header == block || lp.follow == cur) {
+                let innermost = idx + 1 == self.loops.len();
+                match (innermost, lp.header == block) {
+                    (true, true) => {
+                        self.loops[idx].continues += 1;
+                        self.line("continue;".to_string());
+                    }
+                    (true, false) => self.line("break;".to_string()),
+                    (false, _) => self.goto(block),
+                }
+                return;
+            }
+            if self.emitted.contains(&block) {
+                self.goto(block);
+                return;
+            }
+            if !self.body.contains_key(&block) {
+                self.line(format!("// no code at {block:04}"));
+                return;
+            }
+
+            cur = if self.follows.contains_key(&block) { self.write_loop(block) } else { self.write_block(block) };
+        }
+    }
+
+    // Writes the statements of a block and handles its exit, returns the
+    // block control continues with
+    fn write_block(&mut self, block: usize) -> Option<usize> {
+        self.emitted.insert(block);
+        if !self.follows.contains_key(&block) {
+            self.lines.push(Line::Label(self.indent, block));
+        }
+        let (stmts, exit) = self.body[&block].clone();
+        for stmt in &stmts {
+            let text = self.prog.stmt(self.func, stmt);
+            self.line(text);
+        }
+
+        match exit {
+            Exit::Next(next) => {
+                if next.is_none() {
+                    self.line("// runs into data".to_string());
+                }
+                next
+            }
+            Exit::Return => {
+                self.line("return;".to_string());
+                None
+            }
+            Exit::Halt => {
+                self.line("halt();".to_string());
+                None
+            }
+            Exit::Indirect(target) => {
+                let target = self.prog.name(self.func, target);
+                self.line(format!("goto *{target};"));
+                None
+            }
+            Exit::Branch { cond, taken, next } => self.branch(block, cond, taken, next),
+        }
+    }
+
+    // Writes a two-way branch as an if, an if-else, or the condition of a
+    // do-while when it ends a loop body
+    fn branch(&mut self, block: usize, cond: Cond, taken: usize, next: Option<usize>) -> Option<usize> {
+        if next == Some(taken) {
+            return next;
+        }
+        if let Some(lp) = self.loops.last_mut()
+            && lp.indent == self.indent
+            && lp.continues == 0
+        {
+            if taken == lp.header && next == lp.follow {
+                lp.latch = Some(cond);
+                return None;
+            }
+            if next == Some(lp.header) && Some(taken) == lp.follow {
+                lp.latch = Some(cond.negate());
+                return None;
+            }
+        }
+
+        // An arm leaving the innermost loop becomes an if around a break or continue
+        if let Some(lp) = self.loops.last() {
+            let leaves = |to: Option<usize>| to == lp.follow || to == Some(lp.header);
+            if leaves(Some(taken)) != leaves(next) {
+                let (cond, out, stay) = if leaves(Some(taken)) { (cond, Some(taken), next) } else { (cond.negate(), next, Some(taken)) };
+                let text = self.cond(cond);
+                self.line(format!("if ({text}) {{"));
+                self.indent += 1;
+                self.seq(out, None);
+                self.indent -= 1;
+                self.line("}".to_string());
+                return stay;
+            }
+        }
+
+        let merge = immediate(&self.pdom, block);
+        let text = if next == merge { self.cond(cond) } else { self.cond(cond.negate()) };
+        self.line(format!("if ({text}) {{"));
+        self.indent += 1;
+        if next == merge {
+            self.seq(Some(taken), merge);
+        } else {
+            self.seq(next, merge);
+            if Some(taken) != merge {
+                self.indent -= 1;
+                self.line("} else {".to_string());
+                self.indent += 1;
+                self.seq(Some(taken), merge);
+            }
+        }
+        self.indent -= 1;
+        self.line("}".to_string());
+
+        merge
+    }
+
+    // Writes a loop as while (cond), do-while or an endless while with
+    // breaks, returns where it exits to
+    fn write_loop(&mut self, header: usize) -> Option<usize> {
+        let follow = self.follows[&header];
+        self.lines.push(Line::Label(self.indent, header));
+        let open = self.lines.len();
+        self.line("while (1) {".to_string());
+        self.loops.push(Loop { header, follow, indent: self.indent + 1, latch: None, continues: 0 });
+        self.indent += 1;
+
+        match self.body[&header].clone() {
+            (stmts, Exit::Branch { cond, taken, next: Some(next) })
+                if stmts.is_empty() && (Some(taken) == follow || Some(next) == follow) =>
+            {
+                self.emitted.insert(header);
+                let (stay, cond) = if Some(taken) == follow { (next, cond.negate()) } else { (taken, cond) };
+                self.lines[open] = Line::Text(self.indent - 1, format!("while ({}) {{", self.cond(cond)));
+                self.seq(Some(stay), None);
+            }
+            _ => {
+                let next = self.write_block(header);
+                self.seq(next, None);
+            }
+        }
+
+        self.indent -= 1;
+        let lp = self.loops.pop().unwrap();
+        if let Some(Line::Text(indent, text)) = self.lines.last()
+            && *indent == lp.indent
+            && text == "continue;"
+        {
+            self.lines.pop();
+        }
+        match lp.latch {
+            Some(cond) => {
+                self.lines[open] = Line::Text(self.indent, "do {".to_string());
+                let text = self.cond(cond);
+                self.line(format!("}} while ({text});"));
+            }
+            None => self.line("}".to_string()),
+        }
+
+        follow
+    }
+}
+
+// Decompiles a program to C-like pseudo-code. Functions are found by the
+// usual calling convention, a return address stored in a relative base slot
+// followed by a jump, and return through that slot. Memory cells become
+// variables `varN`, relative base slots become locals `localN` counted from
+// the relative base on entry, and arguments stored in the slots after the
+// return address become parameters `pN`. Anything that does not structure
+// into ifs and loops is written with gotos.
+pub fn decompile(prog: &[Word]) -> String {
+    let mut funcs: BTreeMap<usize, Function> = BTreeMap::new();
+    let mut queue = vec![0];
+    while let Some(entry) = queue.pop() {
+        if funcs.contains_key(&entry) {
+            continue;
+        }
+        let func = Function::explore(prog, entry);
+        queue.extend(func.calls.values().map(|call| call.target));
+        funcs.insert(entry, func);
+    }
+
+    let mut reads: BTreeMap<Opnd, usize> = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    for func in funcs.values() {
+        for inst in func.cfg.blocks.values().flat_map(|block| &block.insts) {
+            if seen.insert(inst.addr) {
+                func.reads(inst).filter(|opnd| matches!(opnd, Opnd::Cell(_))).for_each(|opnd| *reads.entry(opnd).or_default() += 1);
+            }
+        }
+    }
+
+    // Parameters are the most arguments any call passes, in the slots after
+    // the return address as the callee sees them
+    let mut params: BTreeMap<usize, (Word, usize)> = BTreeMap::new();
+    for func in funcs.values() {
+        for block in func.cfg.blocks.values() {
+            let (stmts, _) = lower(func, block, &reads);
+            let calls = block.insts.iter().filter_map(|inst| Some((func.calls.get(&inst.addr)?, func.deltas[&inst.addr])));
+            let counts = stmts.iter().filter_map(|stmt| match stmt {
+                Stmt::Call(_, args) => Some(args.len()),
+                _ => None,
+            });
+            for ((call, delta), count) in calls.zip(counts) {
+                if let (Some(slot), Some(delta)) = (call.slot, delta) {
+                    let entry = params.entry(call.target).or_insert((slot - delta, 0));
+                    entry.1 = entry.1.max(count);
+                }
+            }
+        }
+    }
+
+    let prog = Names { funcs: &funcs, params, reads };
+    prog.funcs.values().map(|func| Writer::new(&prog, func).write()).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assemble;
+
+    // Squares its input by a call, prints whether it was small, then counts
+    // down a second input
+    const SQUARES: &str = "
+            IN  [n]
+            ADD [n], #0, rb+1
+            ADD #back, #0, rb+0
+            JZ  #0, #square
+    back:   OUT [res]
+            LT  [n], #10, [t]
+            JZ  [t], #big
+            OUT #1
+            JZ  #0, #done
+    big:    OUT #2
+    done:   IN  [k]
+    count:  ADD [k], #-1, [k]
+            OUT [k]
+            JNZ [k], #count
+            HLT
+    square: RBX #3
+            MUL rb-2, rb-2, rb-1
+            ADD rb-1, #0, [res]
+            RBX #-3
+            JZ  #0, rb+0
+    n:      data 0
+    res:    data 0
+    t:      data 0
+    k:      data 0
+    ";
+
+    // Sums inputs until a zero, stopping early past a limit
+    const SUMMER: &str = "
+    loop:   IN  [x]
+            JZ  [x], #done
+            ADD [sum], [x], [sum]
+            LT  [sum], #100, [t]
+            JNZ [t], #loop
+    done:   OUT [sum]
+            HLT
+    x:      data 0
+    sum:    data 0
+    t:      data 0
+    ";
+
+    #[test]
+    fn calls_ifs_and_loops() {
+        let code = decompile(&assemble(SQUARES).unwrap());
+        assert_eq!(code, "\
+void main() {
+    var56 = input();
+    func_0041(var56);
+    output(var57);
+    if (var56 < 10) {
+        output(1);
+    } else {
+        output(2);
+    }
+    var59 = input();
+    do {
+        var59 = var59 - 1;
+        output(var59);
+    } while (var59 != 0);
+    halt();
+}
+
+void func_0041(p1) {
+    local2 = p1 * p1;
+    var57 = local2;
+    return;
+}
+");
+    }
+
+    #[test]
+    fn loop_with_early_exit() {
+        let code = decompile(&assemble(SUMMER).unwrap());
+        assert_eq!(code, "\
+void main() {
+    do {
+        var19 = input();
+        if (var19 == 0) {
+            break;
+        }
+        var20 = var20 + var19;
+    } while (var20 < 100);
+    output(var20);
+    halt();
+}
+");
+    }
+}
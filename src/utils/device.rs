@@ -0,0 +1,281 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, BufReader, Stdin, Stdout, Write},
+    sync::{Arc, Mutex, mpsc::{Receiver, Sender, SyncSender}},
+};
+use super::intcode::Word;
+
+// Source of values for opcode 3
+pub trait InputPort {
+    // Next input value, None if there is none yet, in which case the machine waits for input
+    fn read(&mut self) -> Option<Word>;
+}
+
+// Sink for values from opcode 4
+pub trait OutputPort {
+    fn write(&mut self, value: Word);
+}
+
+// Closures make devices directly, e.g. `vm.attach_output(|v| println!("{v}"))`
+impl<F: FnMut() -> Option<Word>> InputPort for F {
+    fn read(&mut self) -> Option<Word> {
+        self()
+    }
+}
+
+impl<F: FnMut(Word)> OutputPort for F {
+    fn write(&mut self, value: Word) {
+        self(value)
+    }
+}
+
+impl InputPort for VecDeque<Word> {
+    fn read(&mut self) -> Option<Word> {
+        self.pop_front()
+    }
+}
+
+impl OutputPort for Vec<Word> {
+    fn write(&mut self, value: Word) {
+        self.push(value);
+    }
+}
+
+// Queue shared between the host and one or more machines, every clone is a
+// handle to the same queue. Works as both input and output, so it can wire
+// the output of one machine to the input of another.
+#[derive(Debug, Clone, Default)]
+pub struct SharedQueue(Arc<Mutex<VecDeque<Word>>>);
+
+impl SharedQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, value: Word) {
+        self.0.lock().unwrap().push_back(value);
+    }
+
+    pub fn pop(&self) -> Option<Word> {
+        self.0.lock().unwrap().pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    // Takes everything currently queued
+    pub fn drain(&self) -> Vec<Word> {
+        self.0.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl InputPort for SharedQueue {
+    fn read(&mut self) -> Option<Word> {
+        self.pop()
+    }
+}
+
+impl OutputPort for SharedQueue {
+    fn write(&mut self, value: Word) {
+        self.push(value);
+    }
+}
+
+// A receiver never blocks the machine, an empty channel means waiting for input
+impl InputPort for Receiver<Word> {
+    fn read(&mut self) -> Option<Word> {
+        self.try_recv().ok()
+    }
+}
+
+// Receiver that parks the thread until a value arrives, or the sender hangs up
+pub struct BlockingInput(pub Receiver<Word>);
+
+impl InputPort for BlockingInput {
+    fn read(&mut self) -> Option<Word> {
+        self.0.recv().ok()
+    }
+}
+
+// Values sent after the receiver hangs up are dropped
+impl OutputPort for Sender<Word> {
+    fn write(&mut self, value: Word) {
+        self.send(value).ok();
+    }
+}
+
+impl OutputPort for SyncSender<Word> {
+    fn write(&mut self, value: Word) {
+        self.send(value).ok();
+    }
+}
+
+// Reads lines of text and feeds them as character codes, newline included
+pub struct AsciiInput<R: BufRead> {
+    reader: R,
+    pending: VecDeque<Word>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, pending: VecDeque::new() }
+    }
+}
+
+impl AsciiInput<BufReader<Stdin>> {
+    pub fn stdin() -> Self {
+        Self::new(BufReader::new(io::stdin()))
+    }
+}
+
+impl<R: BufRead> InputPort for AsciiInput<R> {
+    fn read(&mut self) -> Option<Word> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+            self.pending.extend(line.chars().map(|ch| ch as Word));
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+// Writes character codes as text. Anything outside ASCII is written as a
+// number on its own line.
+pub struct AsciiOutput<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> AsciiOutput<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl AsciiOutput<Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> OutputPort for AsciiOutput<W> {
+    fn write(&mut self, value: Word) {
+        match u8::try_from(value) {
+            Ok(byte) if byte.is_ascii() => self.writer.write_all(&[byte]).ok(),
+            _ => writeln!(self.writer, "{value}").ok(),
+        };
+        if value == 10 {
+            self.writer.flush().ok();
+        }
+    }
+}
+
+// Discards output and never has input
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Null;
+
+impl InputPort for Null {
+    fn read(&mut self) -> Option<Word> {
+        None
+    }
+}
+
+impl OutputPort for Null {
+    fn write(&mut self, _: Word) {}
+}
+
+pub type BoxedInput = Box<dyn InputPort + Send>;
+pub type BoxedOutput = Box<dyn OutputPort + Send>;
+
+// Devices attached to a machine. Devices are not cloned with the machine, a
+// clone falls back to its own input and output queues.
+#[derive(Default)]
+pub(crate) struct Ports {
+    pub(crate) input: Option<BoxedInput>,
+    pub(crate) output: Option<BoxedOutput>,
+}
+
+impl Clone for Ports {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for Ports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ports")
+            .field("input", &self.input.as_ref().map(|_| "device"))
+            .field("output", &self.output.as_ref().map(|_| "device"))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Machine, MachineState};
+    use std::sync::mpsc;
+
+    #[test]
+    fn closures_and_shared_queues() {
+        let prog = vec![3,9,1002,9,2,9,4,9,99,0];
+        let queue = SharedQueue::new();
+        let mut vm = Machine::new(&prog);
+        let mut next = 21;
+        vm.attach_input(move || Some(next).inspect(|_| next += 1));
+        vm.attach_output(queue.clone());
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        assert_eq!(queue.drain(), vec![42]);
+        assert!(vm.dump_output().is_empty());
+    }
+
+    #[test]
+    fn channels_and_ascii() {
+        // Echoes inputs until it reads a zero
+        let prog = vec![3,11,1005,11,6,99,4,11,1105,1,0,0];
+        let (tx, rx) = mpsc::channel();
+        let (out_tx, out_rx) = mpsc::channel();
+        let mut vm = Machine::new(&prog);
+        vm.attach_input(rx);
+        vm.attach_output(out_tx);
+        assert_eq!(vm.run(), Ok(MachineState::AwaitingInput));
+
+        for value in [104, 105, 10, 1000] {
+            tx.send(value).unwrap();
+        }
+        assert_eq!(vm.run(), Ok(MachineState::AwaitingInput));
+        let echoed: Vec<Word> = out_rx.try_iter().collect();
+        assert_eq!(echoed, vec![104, 105, 10, 1000]);
+
+        let mut out = AsciiOutput::new(Vec::new());
+        echoed.iter().for_each(|&value| out.write(value));
+        assert_eq!(String::from_utf8(out.into_inner()).unwrap(), "hi\n1000\n");
+
+        let queue = SharedQueue::new();
+        let mut echo = Machine::new(&prog);
+        echo.attach_input(AsciiInput::new("ab".as_bytes()));
+        echo.attach_output(queue.clone());
+        assert_eq!(echo.run(), Ok(MachineState::AwaitingInput));
+        assert_eq!(queue.drain(), vec![97, 98, 10]);
+
+        echo.detach_input();
+        echo.attach_input(Null);
+        echo.input_ext(&[0]);
+        assert_eq!(echo.run(), Ok(MachineState::Halted));
+    }
+}
@@ -0,0 +1,331 @@
+use std::{collections::BTreeMap, fmt};
+use super::intcode::{OpInfo, Word};
+
+// A decoded instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inst {
+    pub addr: usize,          // Address of the instruction word
+    pub word: Word,           // Raw instruction word
+    pub info: &'static OpInfo,
+    pub modes: [Word; 3],     // Parameter modes
+    pub params: [Word; 3],    // Raw parameters, only the first `arity` are meaningful
+}
+
+impl Inst {
+    // Decodes the instruction at an address, None if it is not a valid instruction
+    pub fn decode(mem: &[Word], addr: usize) -> Option<Self> {
+        let word = *mem.get(addr)?;
+        if word < 0 {
+            return None;
+        }
+        let info = OpInfo::lookup(word % 100)?;
+
+        let mut modes = [0; 3];
+        let mut params = [0; 3];
+        let mut code = word / 100;
+        for idx in 0..3 {
+            modes[idx] = code % 10;
+            code /= 10;
+            if idx < info.arity {
+                if modes[idx] > 2 || (info.dest == Some(idx + 1) && modes[idx] == 1) {
+                    return None;
+                }
+                params[idx] = mem.get(addr + idx + 1).copied().unwrap_or(0);
+            }
+        }
+
+        Some(Self { addr, word, info, modes, params })
+    }
+
+    // Number of memory cells taken by the instruction
+    pub fn size(&self) -> usize {
+        self.info.arity + 1
+    }
+
+    // Raw cells of the instruction
+    pub fn words(&self) -> Vec<Word> {
+        let mut words = vec![self.word];
+        words.extend_from_slice(&self.params[..self.info.arity]);
+        words
+    }
+
+    // Is this a conditional or unconditional jump
+    pub fn is_jump(&self) -> bool {
+        matches!(self.info.opcode, 5 | 6)
+    }
+
+    // Jump target, if it is known statically
+    pub fn jump_target(&self) -> Option<usize> {
+        if self.is_jump() && self.modes[1] == 1 && self.params[1] >= 0 {
+            Some(self.params[1] as usize)
+        } else {
+            None
+        }
+    }
+
+    // Is the jump always taken, i.e. the condition is an immediate that always holds
+    pub fn always_jumps(&self) -> bool {
+        self.is_jump() && self.modes[0] == 1 && ((self.info.opcode == 5) == (self.params[0] != 0))
+    }
+
+    // Is the jump never taken, i.e. the condition is an immediate that never holds
+    pub fn never_jumps(&self) -> bool {
+        self.is_jump() && self.modes[0] == 1 && !self.always_jumps()
+    }
+
+    // Can execution continue with the next instruction in memory
+    pub fn falls_through(&self) -> bool {
+        self.info.opcode != 99 && !self.always_jumps()
+    }
+
+    // Address written to in position mode, if any
+    pub fn static_write(&self) -> Option<usize> {
+        let dest = self.info.dest?;
+        if self.modes[dest - 1] == 0 && self.params[dest - 1] >= 0 {
+            Some(self.params[dest - 1] as usize)
+        } else {
+            None
+        }
+    }
+
+    // Renders a single operand as `[addr]`, `#imm` or `rb+off`
+    fn operand(&self, idx: usize, labels: &BTreeMap<usize, String>) -> String {
+        let raw = self.params[idx];
+        match self.modes[idx] {
+            0 => format!("[{raw}]"),
+            1 => match self.jump_target().filter(|_| idx == 1).and_then(|tgt| labels.get(&tgt)) {
+                Some(label) => format!("#{label}"),
+                None => format!("#{raw}"),
+            },
+            _ if raw < 0 => format!("rb{raw}"),
+            _ => format!("rb+{raw}"),
+        }
+    }
+
+    // Renders the instruction as assembly, using labels for known jump targets
+    pub fn render(&self, labels: &BTreeMap<usize, String>) -> String {
+        let operands: Vec<String> = (0..self.info.arity).map(|idx| self.operand(idx, labels)).collect();
+        if operands.is_empty() {
+            self.info.mnemonic.to_string()
+        } else {
+            format!("{:<4}{}", self.info.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(&BTreeMap::new()))
+    }
+}
+
+// A static write by one instruction into the cells of another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfMod {
+    pub writer: usize, // Address of the writing instruction
+    pub target: usize, // Address written to
+    pub inst: usize,   // Address of the instruction containing the target
+}
+
+// A run of the listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Region {
+    Code(Inst),
+    Data { addr: usize, values: Vec<Word> },
+}
+
+impl Region {
+    // Address of the first cell
+    pub fn addr(&self) -> usize {
+        match self {
+            Region::Code(inst) => inst.addr,
+            Region::Data { addr, .. } => *addr,
+        }
+    }
+
+    // Number of cells covered
+    pub fn len(&self) -> usize {
+        match self {
+            Region::Code(inst) => inst.size(),
+            Region::Data { values, .. } => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Result of disassembling a program
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    pub regions: Vec<Region>,
+    pub labels: BTreeMap<usize, String>, // Jump targets by address
+    pub indirect: Vec<usize>,            // Jumps whose targets are unknown statically
+    pub self_mods: Vec<SelfMod>,         // Writes into reachable code
+}
+
+impl Disassembly {
+    // Decoded instructions in address order
+    pub fn instructions(&self) -> impl Iterator<Item = &Inst> {
+        self.regions.iter().filter_map(|region| match region {
+            Region::Code(inst) => Some(inst),
+            Region::Data { .. } => None,
+        })
+    }
+
+    // Instruction starting at an address, if it was reached
+    pub fn inst_at(&self, addr: usize) -> Option<&Inst> {
+        self.instructions().find(|inst| inst.addr == addr)
+    }
+
+    // Listing line of a region: address, raw words, text and notes, without its label
+    pub fn line(&self, region: &Region) -> String {
+        let (addr, words, text) = match region {
+            Region::Code(inst) => (inst.addr, inst.words(), inst.render(&self.labels)),
+            Region::Data { addr, values } => {
+                let text = values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+                (*addr, values.clone(), format!("DATA {text}"))
+            }
+        };
+
+        let mut notes = Vec::new();
+        if self.indirect.contains(&addr) {
+            notes.push("indirect jump".to_string());
+        }
+        for sm in &self.self_mods {
+            if sm.writer == addr {
+                notes.push(format!("modifies code at {:04}", sm.target));
+            }
+            if sm.inst == addr {
+                notes.push(format!("modified by {:04}", sm.writer));
+            }
+        }
+
+        let words = words.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(",");
+        let line = format!("{addr:04}  {words:<24} {text}");
+        if notes.is_empty() {
+            line
+        } else {
+            format!("{line:<60} ; {}", notes.join("; "))
+        }
+    }
+}
+
+// Disassembles a program by following execution from address 0. Jumps are
+// followed when their target is immediate, anything not reached is data.
+pub fn disassemble(prog: &[Word]) -> Disassembly {
+    let mut code: BTreeMap<usize, Inst> = BTreeMap::new();
+    let mut owner: Vec<Option<usize>> = vec![None; prog.len()];
+    let mut labels = BTreeMap::new();
+    let mut indirect = Vec::new();
+    let mut queue = vec![0];
+
+    while let Some(addr) = queue.pop() {
+        if addr >= prog.len() || owner[addr].is_some() {
+            continue;
+        }
+        let Some(inst) = Inst::decode(prog, addr) else {
+            continue;
+        };
+        let end = (addr + inst.size()).min(prog.len());
+        if owner[addr..end].iter().any(|cell| cell.is_some()) {
+            continue;
+        }
+        owner[addr..end].iter_mut().for_each(|cell| *cell = Some(addr));
+        code.insert(addr, inst);
+
+        if inst.is_jump() && !inst.never_jumps() {
+            match inst.jump_target() {
+                Some(target) => {
+                    labels.insert(target, format!("L{target:04}"));
+                    queue.push(target);
+                }
+                None => indirect.push(addr),
+            }
+        }
+        if inst.falls_through() {
+            queue.push(addr + inst.size());
+        }
+    }
+
+    let self_mods = code
+        .values()
+        .filter_map(|inst| {
+            let target = inst.static_write()?;
+            let owner = (*owner.get(target)?)?;
+            Some(SelfMod { writer: inst.addr, target, inst: owner })
+        })
+        .collect();
+
+    let mut regions = Vec::new();
+    let mut addr = 0;
+    while addr < prog.len() {
+        if let Some(inst) = code.get(&addr) {
+            regions.push(Region::Code(*inst));
+            addr += inst.size();
+        } else {
+            let start = addr;
+            addr += 1;
+            while addr < prog.len() && addr - start < 8 && !code.contains_key(&addr) && !labels.contains_key(&addr) {
+                addr += 1;
+            }
+            regions.push(Region::Data { addr: start, values: prog[start..addr].to_vec() });
+        }
+    }
+    indirect.sort_unstable();
+
+    Disassembly { regions, labels, indirect, self_mods }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for region in &self.regions {
+            if let Some(label) = self.labels.get(&region.addr()) {
+                writeln!(f, "{label}:")?;
+            }
+            writeln!(f, "{}", self.line(region))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_follows_jumps_and_marks_data() {
+        let prog = vec![3,16,1105,1,9,4,16,99,0,1002,16,2,16,1106,0,5];
+        let dis = disassemble(&prog);
+        let lines: Vec<String> = dis.to_string().lines().map(|l| l.trim_end().to_string()).collect();
+        assert_eq!(lines, vec![
+            "0000  3,16                     IN  [16]",
+            "0002  1105,1,9                 JNZ #1, #L0009",
+            "L0005:",
+            "0005  4,16                     OUT [16]",
+            "0007  99                       HLT",
+            "0008  0                        DATA 0",
+            "L0009:",
+            "0009  1002,16,2,16             MUL [16], #2, [16]",
+            "0013  1106,0,5                 JZ  #0, #L0005",
+        ]);
+    }
+
+    #[test]
+    fn reports_self_modification() {
+        let prog = vec![1101,0,4,5,104,0,99];
+        let dis = disassemble(&prog);
+        assert_eq!(dis.self_mods, vec![SelfMod { writer: 0, target: 5, inst: 4 }]);
+        assert!(dis.to_string().contains("modified by 0000"));
+    }
+
+    #[test]
+    fn relative_operands_and_indirect_jumps() {
+        let prog = vec![109,5,2106,0,-1,99];
+        let dis = disassemble(&prog);
+        assert_eq!(dis.inst_at(2).unwrap().to_string(), "JZ  #0, rb-1");
+        assert_eq!(dis.indirect, vec![2]);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{collections::{BTreeMap, BTreeSet}, fmt::Write};
+use super::disasm::{Inst, disassemble};
+use super::intcode::Word;
+
+// How control gets from one block to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Taken,       // The jump ending the block was taken
+    FallThrough, // Execution continued with the next instruction in memory
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: usize, // Start of the source block
+    pub to: usize,   // Start of the target block
+    pub kind: EdgeKind,
+}
+
+// A run of instructions entered only at the top and left only at the bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub indirect: bool, // Ends in a jump whose target is unknown statically
+}
+
+impl Block {
+    // Address of the first instruction
+    pub fn start(&self) -> usize {
+        self.insts[0].addr
+    }
+
+    // Address just past the last instruction
+    pub fn end(&self) -> usize {
+        let last = self.last();
+        last.addr + last.size()
+    }
+
+    pub fn last(&self) -> &Inst {
+        self.insts.last().unwrap()
+    }
+}
+
+// Control-flow graph of the code reachable from address 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>, // Blocks by start address
+    pub edges: Vec<Edge>,
+}
+
+impl Cfg {
+    // Block containing an address
+    pub fn block_at(&self, addr: usize) -> Option<&Block> {
+        let (_, block) = self.blocks.range(..=addr).next_back()?;
+        (addr < block.end()).then_some(block)
+    }
+
+    // Edges leaving a block
+    pub fn successors(&self, start: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == start)
+    }
+
+    // Edges entering a block
+    pub fn predecessors(&self, start: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.to == start)
+    }
+
+    // Graphviz DOT of the graph. Taken edges are solid, fall-through edges
+    // dashed, and indirect jumps point at a node for the unknown target.
+    pub fn to_dot(&self) -> String {
+        let labels: BTreeMap<usize, String> = self.blocks.keys().map(|&addr| (addr, format!("L{addr:04}"))).collect();
+        let mut dot = String::from("digraph intcode {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for (&start, block) in &self.blocks {
+            let text: String = block.insts.iter().map(|inst| format!("{:04}  {}\\l", inst.addr, inst.render(&labels))).collect();
+            writeln!(dot, "    b{start} [label=\"L{start:04}:\\l{text}\"];").unwrap();
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Taken => "label=\"taken\"",
+                EdgeKind::FallThrough => "label=\"fall-through\", style=dashed",
+            };
+            writeln!(dot, "    b{} -> b{} [{style}];", edge.from, edge.to).unwrap();
+        }
+        if self.blocks.values().any(|block| block.indirect) {
+            writeln!(dot, "    unresolved [shape=plaintext, label=\"?\"];").unwrap();
+            for &start in self.blocks.iter().filter(|(_, block)| block.indirect).map(|(start, _)| start) {
+                writeln!(dot, "    b{start} -> unresolved [label=\"indirect\", style=dotted];").unwrap();
+            }
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+// Splits the reachable code into basic blocks. A block starts at address 0,
+// at a jump target and after a jump or halt, and ends at a jump or halt or
+// where the next block starts. Code written at run time is not seen.
+pub fn control_flow(prog: &[Word]) -> Cfg {
+    let dis = disassemble(prog);
+    let insts: Vec<Inst> = dis.instructions().copied().collect();
+    let mut leaders: BTreeSet<usize> = dis.labels.keys().copied().collect();
+    leaders.insert(0);
+    for inst in insts.iter().filter(|inst| inst.is_jump() || !inst.falls_through()) {
+        leaders.insert(inst.addr + inst.size());
+    }
+
+    let mut blocks: BTreeMap<usize, Block> = BTreeMap::new();
+    let mut current: Vec<Inst> = Vec::new();
+    for inst in insts {
+        let contiguous = current.last().is_some_and(|last| last.addr + last.size() == inst.addr);
+        if !current.is_empty() && (leaders.contains(&inst.addr) || !contiguous) {
+            let block = Block { insts: std::mem::take(&mut current), indirect: false };
+            blocks.insert(block.start(), block);
+        }
+        current.push(inst);
+    }
+    if !current.is_empty() {
+        let block = Block { insts: current, indirect: false };
+        blocks.insert(block.start(), block);
+    }
+
+    let mut edges = Vec::new();
+    for (&start, block) in &mut blocks {
+        let last = *block.last();
+        if last.is_jump() && !last.never_jumps() {
+            match last.jump_target() {
+                Some(to) => edges.push(Edge { from: start, to, kind: EdgeKind::Taken }),
+                None => block.indirect = true,
+            }
+        }
+        if last.falls_through() {
+            edges.push(Edge { from: start, to: block.end(), kind: EdgeKind::FallThrough });
+        }
+    }
+    // Jumps into the middle of an instruction, or off the end, lead to no block
+    edges.retain(|edge| blocks.contains_key(&edge.to));
+
+    Cfg { blocks, edges }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assemble;
+
+    // Doubles a non-zero input, outputs it, then jumps through a pointer
+    const BRANCHY: &str = "
+            IN  [n]
+            JZ  [n], #zero
+            MUL [n], #2, [n]
+    zero:   OUT [n]
+            JNZ [n], [ret]
+            HLT
+    n:      data 0
+    ret:    data 0
+    ";
+
+    #[test]
+    fn blocks_edges_and_dot() {
+        let cfg = control_flow(&assemble(BRANCHY).unwrap());
+        let starts: Vec<usize> = cfg.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0, 5, 9, 14]);
+        assert_eq!(cfg.block_at(12).unwrap().start(), 9);
+        assert!(cfg.block_at(15).is_none());
+        assert!(cfg.blocks[&9].indirect && !cfg.blocks[&0].indirect);
+        assert_eq!(cfg.edges, vec![
+            Edge { from: 0, to: 9, kind: EdgeKind::Taken },
+            Edge { from: 0, to: 5, kind: EdgeKind::FallThrough },
+            Edge { from: 5, to: 9, kind: EdgeKind::FallThrough },
+            Edge { from: 9, to: 14, kind: EdgeKind::FallThrough },
+        ]);
+        assert_eq!(cfg.predecessors(9).count(), 2);
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph intcode {"));
+        assert!(dot.contains("    b0 [label=\"L0000:\\l0000  IN  [15]\\l0002  JZ  [15], #L0009\\l\"];"));
+        assert!(dot.contains("    b0 -> b9 [label=\"taken\"];"));
+        assert!(dot.contains("    b5 -> b9 [label=\"fall-through\", style=dashed];"));
+        assert!(dot.contains("    b9 -> unresolved [label=\"indirect\", style=dotted];"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+use super::intcode::Word;
+
+// Instructions between clock checks under a time budget
+const CLOCK_EVERY: u32 = 1024;
+
+// How much a machine may execute before it stops with OutOfFuel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    Unlimited,
+    Instructions(u64), // A number of instructions
+    Time(Duration),    // Wall-clock time, counted from when the budget is given
+}
+
+// Fuel left to a machine. A time budget is only checked every CLOCK_EVERY
+// instructions, so a machine may overrun it by that many.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Fuel {
+    left: Option<u64>,
+    deadline: Option<Instant>,
+    tick: u32,
+}
+
+impl Fuel {
+    pub(crate) fn set(&mut self, budget: Budget) {
+        *self = Self::default();
+        match budget {
+            Budget::Unlimited => {}
+            Budget::Instructions(count) => self.left = Some(count),
+            Budget::Time(time) => self.deadline = Some(Instant::now() + time),
+        }
+    }
+
+    pub(crate) fn is_limited(&self) -> bool {
+        self.left.is_some() || self.deadline.is_some()
+    }
+
+    // Instructions left under an instruction budget
+    pub(crate) fn left(&self) -> Option<u64> {
+        self.left
+    }
+
+    // Whether another instruction may run
+    pub(crate) fn has_fuel(&mut self) -> bool {
+        if self.left == Some(0) {
+            return false;
+        }
+        if let Some(deadline) = self.deadline {
+            if self.tick == 0 && Instant::now() >= deadline {
+                // Stays empty until refuelled
+                self.deadline = None;
+                self.left = Some(0);
+                return false;
+            }
+            self.tick = (self.tick + 1) % CLOCK_EVERY;
+        }
+        true
+    }
+
+    // Uses up one instruction
+    pub(crate) fn burn(&mut self) {
+        if let Some(left) = &mut self.left {
+            *left -= 1;
+        }
+    }
+}
+
+// Instructions executed since the machine booted, per opcode. Only
+// instructions that completed are counted, not ones that blocked or faulted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counters {
+    ops: [u64; 10], // Opcodes 1 to 9, and 99 at index 0
+}
+
+impl Counters {
+    // Instructions executed in total
+    pub fn instructions(&self) -> u64 {
+        self.ops.iter().sum()
+    }
+
+    // Times an opcode was executed
+    pub fn count(&self, opcode: Word) -> u64 {
+        match opcode {
+            1..=9 => self.ops[opcode as usize],
+            99 => self.ops[0],
+            _ => 0,
+        }
+    }
+
+    // Opcodes executed at least once, with their counts, in opcode order
+    pub fn iter(&self) -> impl Iterator<Item = (Word, u64)> + '_ {
+        (1..=9).chain([99]).map(|opcode| (opcode, self.count(opcode))).filter(|&(_, count)| count > 0)
+    }
+
+    pub(crate) fn record(&mut self, opcode: Word) {
+        self.ops[if opcode == 99 { 0 } else { opcode as usize }] += 1;
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+use super::device::InputPort;
+use super::intcode::{Machine, MachineState, Program, VmError, Word};
+use super::network::SLICE;
+
+// Input shared between a machine and its feeders
+#[derive(Debug, Default)]
+struct Channel {
+    queue: VecDeque<Word>,
+    waker: Option<Waker>, // Task waiting for input
+    closed: bool,
+}
+
+// Sends input to an async machine and wakes the task awaiting it
+#[derive(Debug, Clone)]
+pub struct Feeder(Arc<Mutex<Channel>>);
+
+impl Feeder {
+    pub fn send(&self, value: Word) {
+        self.send_all(&[value]);
+    }
+
+    pub fn send_all(&self, values: &[Word]) {
+        let mut chan = self.0.lock().unwrap();
+        chan.queue.extend(values);
+        if let Some(waker) = chan.waker.take() {
+            waker.wake();
+        }
+    }
+
+    // No more input will come, a machine that runs out of input then ends its output
+    pub fn close(&self) {
+        let mut chan = self.0.lock().unwrap();
+        chan.closed = true;
+        if let Some(waker) = chan.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl InputPort for Feeder {
+    fn read(&mut self) -> Option<Word> {
+        self.0.lock().unwrap().queue.pop_front()
+    }
+}
+
+// A machine run by an async task. Awaiting input yields the task until a
+// feeder sends some, and a long stretch without I/O yields every SLICE
+// instructions. Execution is the interpreter's, so the semantics are those of
+// Machine. Works with any executor. Output is read from the output queue, so
+// the machine should not have an output device attached.
+#[derive(Debug)]
+pub struct AsyncMachine {
+    vm: Machine,
+    input: Feeder,
+}
+
+impl AsyncMachine {
+    pub fn new(prog: &Program) -> Self {
+        Self::from_machine(Machine::new(prog))
+    }
+
+    // Wraps a machine, attaching the async input as its input device. Values
+    // already in its input queue are read first.
+    pub fn from_machine(mut vm: Machine) -> Self {
+        let input = Feeder(Arc::default());
+        vm.attach_input(input.clone());
+        Self { vm, input }
+    }
+
+    // A handle that sends input to the machine
+    pub fn feeder(&self) -> Feeder {
+        self.input.clone()
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.vm
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.vm
+    }
+
+    // Unwraps the machine, detaching the async input
+    pub fn into_machine(mut self) -> Machine {
+        self.vm.detach_input();
+        self.vm
+    }
+
+    // Polls for the next output, the shape of Stream::poll_next. Ends with None
+    // once the machine halts, runs out of input after its feeders closed, or
+    // stops otherwise. A fault is yielded once and then ends the stream.
+    pub fn poll_output(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Word, VmError>>> {
+        if let Some(value) = self.vm.pop_output() {
+            return Poll::Ready(Some(Ok(value)));
+        }
+        if let MachineState::Faulted(_) = self.vm.state() {
+            return Poll::Ready(None);
+        }
+
+        match self.poll_run(cx, true) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err))),
+            Poll::Ready(Ok(_)) => Poll::Ready(self.vm.pop_output().map(Ok)),
+        }
+    }
+
+    // Next output, None at the end of the output
+    pub fn next_output(&mut self) -> NextOutput<'_> {
+        NextOutput(self)
+    }
+
+    // Runs like Machine::run, to a halt, the end of input after the feeders
+    // closed, or any other stop. Output collects in the machine's output queue.
+    pub fn run(&mut self) -> Run<'_> {
+        Run(self)
+    }
+
+    // Runs a slice, stopping early after an output if asked to. Ready with the
+    // state the machine stopped in, or Running after an output.
+    fn poll_run(&mut self, cx: &mut Context<'_>, until_output: bool) -> Poll<Result<MachineState, VmError>> {
+        let mut count = 0;
+        let state = self.vm.run_until(|vm| {
+            count += 1;
+            (until_output && vm.last_step().output.is_some()) || count >= SLICE
+        })?;
+
+        match state {
+            MachineState::Running if until_output && self.vm.output_len() > 0 => Poll::Ready(Ok(state)),
+            MachineState::Running => {
+                // Let other tasks run
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            MachineState::AwaitingInput => {
+                let mut chan = self.input.0.lock().unwrap();
+                if !chan.queue.is_empty() {
+                    // Input arrived after the machine looked
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                } else if chan.closed {
+                    Poll::Ready(Ok(state))
+                } else {
+                    chan.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+            state => Poll::Ready(Ok(state)),
+        }
+    }
+}
+
+// Future of the next output of an async machine
+pub struct NextOutput<'a>(&'a mut AsyncMachine);
+
+impl Future for NextOutput<'_> {
+    type Output = Option<Result<Word, VmError>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().0.poll_output(cx)
+    }
+}
+
+// Future of an async machine running until it stops
+pub struct Run<'a>(&'a mut AsyncMachine);
+
+impl Future for Run<'_> {
+    type Output = Result<MachineState, VmError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().0.poll_run(cx, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        task::Wake,
+        thread,
+        time::Duration,
+    };
+
+    // Wakes a thread parked in `block_on`
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = std::pin::pin!(fut);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+            thread::park();
+        }
+    }
+
+    // Flags a task of `join_all` for polling
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Polls every task on one thread until all are done, panics if they stall
+    fn join_all<T>(tasks: Vec<Pin<Box<dyn Future<Output = T> + '_>>>) -> Vec<T> {
+        let mut tasks: Vec<_> = tasks.into_iter().map(|task| (Some(task), Arc::new(Flag(AtomicBool::new(true))))).collect();
+        let mut results: Vec<Option<T>> = tasks.iter().map(|_| None).collect();
+        while results.iter().any(Option::is_none) {
+            let mut polled = false;
+            for ((task, flag), result) in tasks.iter_mut().zip(&mut results) {
+                if !flag.0.swap(false, Ordering::SeqCst) || task.is_none() {
+                    continue;
+                }
+                polled = true;
+                let waker = Waker::from(flag.clone());
+                if let Poll::Ready(out) = task.as_mut().unwrap().as_mut().poll(&mut Context::from_waker(&waker)) {
+                    *result = Some(out);
+                    *task = None;
+                }
+            }
+            assert!(polled, "tasks stalled");
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    #[test]
+    fn outputs_follow_async_input() {
+        // Doubles every input
+        let double = vec![3,9,102,2,9,9,4,9,1105,1,0];
+        let mut vm = AsyncMachine::new(&double);
+        let feeder = vm.feeder();
+        let sender = thread::spawn(move || {
+            for value in 1..=3 {
+                thread::sleep(Duration::from_millis(5));
+                feeder.send(value);
+            }
+            feeder.close();
+        });
+
+        let outputs = block_on(async {
+            let mut outputs = Vec::new();
+            while let Some(value) = vm.next_output().await {
+                outputs.push(value.unwrap());
+            }
+            outputs
+        });
+        sender.join().unwrap();
+        assert_eq!(outputs, vec![2, 4, 6]);
+        assert_eq!(vm.machine().state(), MachineState::AwaitingInput);
+
+        // Counts down from a million without I/O, yielding along the way
+        let mut count = AsyncMachine::new(&vec![1001,8,-1,8,1005,8,0,99,1_000_000]);
+        assert_eq!(block_on(count.run()), Ok(MachineState::Halted));
+        assert_eq!(count.machine().counters().instructions(), 2_000_001);
+    }
+
+    #[test]
+    fn feedback_loop_of_tasks() {
+        let prog: Program = vec![
+            3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5
+        ];
+        let mut amps: Vec<AsyncMachine> = [9, 8, 7, 6, 5].into_iter().map(|phase| {
+            let amp = AsyncMachine::new(&prog);
+            amp.feeder().send(phase);
+            amp
+        }).collect();
+        let feeders: Vec<Feeder> = amps.iter().map(AsyncMachine::feeder).collect();
+        feeders[0].send(0);
+
+        let tasks = amps.iter_mut().enumerate().map(|(idx, amp)| {
+            let next = feeders[(idx + 1) % feeders.len()].clone();
+            Box::pin(async move {
+                let mut last = None;
+                while let Some(value) = amp.next_output().await {
+                    let value = value.unwrap();
+                    next.send(value);
+                    last = Some(value);
+                }
+                last
+            }) as Pin<Box<dyn Future<Output = Option<Word>>>>
+        }).collect();
+
+        assert_eq!(join_all(tasks)[4], Some(139629729));
+        assert!(amps.iter().all(|amp| amp.machine().state() == MachineState::Halted));
+
+        let mut bad = AsyncMachine::new(&vec![42]);
+        assert_eq!(block_on(bad.next_output()), Some(Err(VmError::InvalidOpcode { ip: 0, word: 42, opcode: 42 })));
+        assert_eq!(block_on(bad.next_output()), None);
+    }
+}
@@ -1,115 +1,721 @@
-use std::{collections::VecDeque, ops::RangeInclusive};
+use std::{collections::VecDeque, fmt, ops::RangeInclusive, sync::Arc, time::Instant};
+use super::cache::DecodeCache;
+use super::coverage::Coverage;
+use super::device::{BoxedInput, BoxedOutput, InputPort, OutputPort, Ports};
+use super::fuel::{Budget, Counters, Fuel};
+use super::memory::{Backend, Memory};
+use super::probe::{Access, Probes, Trap, WatchKind};
+use super::profile::Profile;
+use super::snapshot::Snapshot;
+use super::trace::Trace;
 
-pub type Program = Vec<isize>;
-pub type Memory = Vec<isize>;
+// An Intcode value. Intcode needs at least 64 bits, the wide-words feature
+// makes it 128 bits wide for programs that need more.
+#[cfg(not(feature = "wide-words"))]
+pub type Word = i64;
+#[cfg(feature = "wide-words")]
+pub type Word = i128;
+
+pub type Program = Vec<Word>;
+
+// How ADD, MUL, RBX and relative addressing treat results that do not fit in a Word
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Arithmetic {
+    #[default]
+    Wrapping, // Wrap around, as release builds always did
+    Checked,  // Fault with ArithmeticOverflow
+}
+
+// Static description of an opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpInfo {
+    pub opcode: Word,
+    pub mnemonic: &'static str,
+    pub arity: usize,        // Number of parameters
+    pub dest: Option<usize>, // Parameter that is written to, counted from 1
+}
+
+pub const OPCODES: [OpInfo; 10] = [
+    OpInfo { opcode: 1,  mnemonic: "ADD", arity: 3, dest: Some(3) },
+    OpInfo { opcode: 2,  mnemonic: "MUL", arity: 3, dest: Some(3) },
+    OpInfo { opcode: 3,  mnemonic: "IN",  arity: 1, dest: Some(1) },
+    OpInfo { opcode: 4,  mnemonic: "OUT", arity: 1, dest: None },
+    OpInfo { opcode: 5,  mnemonic: "JNZ", arity: 2, dest: None },
+    OpInfo { opcode: 6,  mnemonic: "JZ",  arity: 2, dest: None },
+    OpInfo { opcode: 7,  mnemonic: "LT",  arity: 3, dest: Some(3) },
+    OpInfo { opcode: 8,  mnemonic: "EQ",  arity: 3, dest: Some(3) },
+    OpInfo { opcode: 9,  mnemonic: "RBX", arity: 1, dest: None },
+    OpInfo { opcode: 99, mnemonic: "HLT", arity: 0, dest: None },
+];
+
+impl OpInfo {
+    // Looks up an opcode
+    pub fn lookup(opcode: Word) -> Option<&'static OpInfo> {
+        OPCODES.iter().find(|info| info.opcode == opcode)
+    }
+
+    // Looks up a mnemonic, ignoring case
+    pub fn by_mnemonic(mnemonic: &str) -> Option<&'static OpInfo> {
+        OPCODES.iter().find(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic))
+    }
+}
+
+// Execution state of the machine, as reported by run and resume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineState {
+    Running,          // Ready to execute, or executing
+    AwaitingInput,    // Blocked on opcode 3 with an empty input queue
+    OutputFull,       // Blocked on opcode 4 with a full bounded output queue
+    OutputReady,      // Stopped after opcode 4 produced a value
+    Paused,           // Paused by the host, only resume will continue
+    Break(Trap),      // Stopped by a breakpoint or watchpoint
+    OutOfFuel,        // Used up its fuel budget, refuel and run to continue
+    Halted,           // Hit opcode 99, or ran off the end of the code sequence
+    Faulted(VmError), // Stopped by a fault, the machine will not run again
+}
+
+impl fmt::Display for MachineState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineState::Running => write!(f, "running"),
+            MachineState::AwaitingInput => write!(f, "awaiting input"),
+            MachineState::OutputFull => write!(f, "output full"),
+            MachineState::OutputReady => write!(f, "output ready"),
+            MachineState::Paused => write!(f, "paused"),
+            MachineState::Break(trap) => write!(f, "stopped at {trap}"),
+            MachineState::OutOfFuel => write!(f, "out of fuel"),
+            MachineState::Halted => write!(f, "halted"),
+            MachineState::Faulted(err) => write!(f, "faulted, {err}"),
+        }
+    }
+}
+
+// A single operand of an executed instruction, resolved according to its mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Operand {
+    pub mode: Word,          // Parameter mode
+    pub raw: Word,           // Parameter as it appears in the code sequence
+    pub addr: Option<usize>, // Memory address referenced, None in immediate mode
+    pub value: Word,         // Value read, or written for a destination parameter
+}
+
+// Record of what a single executed instruction did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Step {
+    pub ip: usize,                     // Address of the instruction
+    pub word: Word,                    // Raw instruction word
+    pub opcode: Word,                  // Decoded opcode
+    pub modes: [Word; 3],              // Decoded parameter modes
+    pub arity: usize,                  // Number of parameters of the instruction
+    pub read: usize,                   // Number of operands used, fewer than `arity` for a jump not taken
+    pub operands: [Operand; 3],        // Operands, mode and raw of the first `arity`, address and value of the first `read`
+    pub write: Option<(usize, Word)>,  // Memory written as (address, value)
+    pub input: Option<Word>,           // Input consumed
+    pub output: Option<Word>,          // Output produced
+    pub next_ip: usize,                // Instruction pointer after execution
+    pub rb: Word,                      // Relative base after execution
+}
+
+impl Step {
+    // The operands actually used by the instruction
+    pub fn operands(&self) -> &[Operand] {
+        &self.operands[..self.read]
+    }
+
+    // Raw instruction words, from the opcode through every parameter
+    pub fn words(&self) -> Vec<Word> {
+        std::iter::once(self.word).chain(self.operands[..self.arity].iter().map(|op| op.raw)).collect()
+    }
+}
+
+// Faults raised while executing a program. Every variant carries the faulting
+// instruction pointer, the raw instruction word and its decoded opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    InvalidOpcode { ip: usize, word: Word, opcode: Word },
+    InvalidMode { ip: usize, word: Word, opcode: Word, param: usize, mode: Word },
+    NegativeAddress { ip: usize, word: Word, opcode: Word, param: usize, mode: Word, addr: Word },
+    WriteInImmediateMode { ip: usize, word: Word, opcode: Word, param: usize },
+    MemoryLimit { ip: usize, word: Word, opcode: Word, param: usize, addr: usize },
+    ArithmeticOverflow { ip: usize, word: Word, opcode: Word },
+}
+
+impl VmError {
+    // Instruction pointer of the faulting instruction
+    pub fn ip(&self) -> usize {
+        match *self {
+            VmError::InvalidOpcode { ip, .. }
+            | VmError::InvalidMode { ip, .. }
+            | VmError::NegativeAddress { ip, .. }
+            | VmError::WriteInImmediateMode { ip, .. }
+            | VmError::MemoryLimit { ip, .. }
+            | VmError::ArithmeticOverflow { ip, .. } => ip,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            VmError::InvalidOpcode { ip, word, opcode } => {
+                write!(f, "invalid opcode {opcode} (word {word}) at ip {ip}")
+            }
+            VmError::InvalidMode { ip, word, opcode, param, mode } => {
+                write!(f, "invalid mode {mode} for parameter {param} of opcode {opcode} (word {word}) at ip {ip}")
+            }
+            VmError::NegativeAddress { ip, word, opcode, param, mode, addr } => {
+                write!(f, "negative address {addr} from parameter {param} (mode {mode}) of opcode {opcode} (word {word}) at ip {ip}")
+            }
+            VmError::WriteInImmediateMode { ip, word, opcode, param } => {
+                write!(f, "write to parameter {param} in immediate mode by opcode {opcode} (word {word}) at ip {ip}")
+            }
+            VmError::MemoryLimit { ip, word, opcode, param, addr } => {
+                write!(f, "address {addr} from parameter {param} of opcode {opcode} (word {word}) at ip {ip} is past the memory limit")
+            }
+            VmError::ArithmeticOverflow { ip, word, opcode } => {
+                write!(f, "arithmetic overflow in opcode {opcode} (word {word}) at ip {ip}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+// A token in the program text that is not an integer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub index: usize,  // Position of the token in the comma-separated list
+    pub token: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid token {:?} at index {}", self.token, self.index)
+    }
+}
+
+impl std::error::Error for ParseError {}
 
 // Intcode Virtual Machine
 #[derive(Debug, Clone)]
 pub struct Machine {
-    ip: usize,           // Instruction Pointer
-    cs: Memory,          // Code Sequence
-    iq: VecDeque<isize>, // Input queue
-    oq: Vec<isize>,      // Output queue
-    pm: [isize; 3],      // Parameter mode
-    os: bool,            // Operating System running?
-    ps: bool,            // Pause operations (e.g. wait for input)
-    rb: isize,           // Relative base
+    ip: usize,            // Instruction Pointer
+    cs: Memory,           // Code Sequence
+    iq: VecDeque<Word>,   // Input queue
+    oq: VecDeque<Word>,   // Output queue
+    ol: Option<usize>,    // Output limit, when the output queue is bounded
+    pm: [Word; 3],        // Parameter mode
+    st: MachineState,     // Machine state
+    rb: Word,             // Relative base
+    ex: Step,             // Execution record of the last instruction
+    tr: Option<Trace>,    // Execution trace, when enabled
+    pf: Option<Profile>,  // Execution profile, when enabled
+    cv: Option<Coverage>, // Code coverage, when enabled
+    pb: Probes,           // Breakpoints and watchpoints
+    io: Ports,            // Attached I/O devices, the queues are used when none are attached
+    fu: Fuel,             // Fuel budget
+    ct: Counters,         // Instructions executed per opcode
+    am: Arithmetic,       // Overflow handling
+    dc: DecodeCache,      // Decoded instruction words
 }
 
+// Memory limit of machines made by `new`, far past what any puzzle uses, so a
+// stray address faults with MemoryLimit instead of exhausting the host
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 20;
+
 impl Machine {
     // *** All the basic shit ***
-    // Create a new virtual machine
+    // Create a new virtual machine with dense memory up to DEFAULT_MEMORY_LIMIT
     pub fn new(prog: &Program) -> Self {
-        Self { 
+        Self::with_memory(Memory::dense(prog).with_limit(Some(DEFAULT_MEMORY_LIMIT)))
+    }
+
+    // Create a new virtual machine over prepared memory, e.g. a paged or limited one
+    pub fn with_memory(cs: Memory) -> Self {
+        Self {
             ip: 0,
-            cs: prog.clone(),
+            cs,
             iq: VecDeque::new(),
-            oq: Vec::new(),
+            oq: VecDeque::new(),
+            ol: None,
             pm: [0; 3],
-            os: true,
-            ps: false,
+            st: MachineState::Running,
             rb: 0,
+            ex: Step::default(),
+            tr: None,
+            pf: None,
+            cv: None,
+            pb: Probes::default(),
+            io: Ports::default(),
+            fu: Fuel::default(),
+            ct: Counters::default(),
+            am: Arithmetic::default(),
+            dc: DecodeCache::default(),
+        }
+    }
+
+    // Run the machine until it halts, pauses, needs input or faults
+    pub fn run(&mut self) -> Result<MachineState, VmError> {
+        self.run_until(|_| false)
+    }
+
+    // Execute exactly one instruction and report what it did. Returns None if
+    // nothing was executed because the machine is halted, paused, out of fuel
+    // or blocked on I/O.
+    pub fn step(&mut self) -> Result<Option<Step>, VmError> {
+        if !self.ready()? || !self.fuel_check() {
+            return Ok(None);
+        }
+
+        let started = self.pf.is_some().then(Instant::now);
+        let exec = self.exec();
+        self.clock(started);
+        exec?;
+        if self.blocked() {
+            Ok(None)
+        } else {
+            Ok(Some(self.ex))
+        }
+    }
+
+    // Run until the next output is produced, stopping with OutputReady
+    pub fn run_until_output(&mut self) -> Result<MachineState, VmError> {
+        self.run_until(|vm| vm.ex.output.is_some())?;
+        if self.st == MachineState::Running && self.ex.output.is_some() {
+            self.st = MachineState::OutputReady;
+        }
+
+        Ok(self.st)
+    }
+
+    // Run for at most a number of cycles, stopping as Running if they run out
+    pub fn run_for(&mut self, cycles: usize) -> Result<MachineState, VmError> {
+        if cycles == 0 {
+            return Ok(self.st);
+        }
+
+        let mut count = 0;
+        self.run_until(|_| {
+            count += 1;
+            count >= cycles
+        })
+    }
+
+    // Run until the predicate holds after an instruction, stopping as Running if it does
+    pub fn run_until<F: FnMut(&Machine) -> bool>(&mut self, pred: F) -> Result<MachineState, VmError> {
+        if !self.ready()? {
+            return Ok(self.st);
+        }
+
+        let started = self.pf.is_some().then(Instant::now);
+        let run = self.exec_until(pred);
+        self.clock(started);
+        run?;
+
+        Ok(self.st)
+    }
+
+    // The interpreter loop of run_until
+    fn exec_until<F: FnMut(&Machine) -> bool>(&mut self, mut pred: F) -> Result<(), VmError> {
+        while self.st == MachineState::Running {
+            if !self.fuel_check() {
+                break;
+            }
+            if self.pb.has_breaks()
+                && let Some(trap) = self.pb.check_break(self, self.ip)
+            {
+                self.st = MachineState::Break(trap);
+                break;
+            }
+            self.exec()?;
+            if pred(self) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Adds the time since `started` to the profile, if profiling
+    fn clock(&mut self, started: Option<Instant>) {
+        if let (Some(pf), Some(started)) = (&mut self.pf, started) {
+            pf.clock(started.elapsed(), self.st);
+        }
+    }
+
+    // Prepares the machine to execute, returns false if it may not
+    fn ready(&mut self) -> Result<bool, VmError> {
+        match self.st {
+            MachineState::Halted | MachineState::Paused => Ok(false),
+            MachineState::Faulted(err) => Err(err),
+            state => {
+                if let MachineState::Break(_) = state {
+                    self.pb.skip = Some(self.ip);
+                }
+                self.st = MachineState::Running;
+                Ok(true)
+            }
+        }
+    }
+
+    // Checks there is fuel for another instruction, stopping with OutOfFuel if not
+    fn fuel_check(&mut self) -> bool {
+        if !self.fu.has_fuel() {
+            self.st = MachineState::OutOfFuel;
+            return false;
+        }
+        true
+    }
+
+    // Whether the last instruction was held back waiting on input or output space
+    fn blocked(&self) -> bool {
+        matches!(self.st, MachineState::AwaitingInput | MachineState::OutputFull)
+    }
+
+    // Executes one instruction, recording any fault in the machine state
+    fn exec(&mut self) -> Result<(), VmError> {
+        if let Err(err) = self.cycle() {
+            self.st = MachineState::Faulted(err);
+            return Err(err);
         }
+        self.ex.next_ip = self.ip;
+        self.ex.rb = self.rb;
+        self.pb.skip = None;
+
+        if let Some(trap) = self.pb.hit.take()
+            && self.st == MachineState::Running
+        {
+            self.st = MachineState::Break(trap);
+        }
+
+        if !self.blocked() {
+            self.fu.burn();
+            self.ct.record(self.ex.opcode);
+            if let Some(tr) = &mut self.tr {
+                tr.push(self.ex);
+            }
+            if let Some(pf) = &mut self.pf {
+                pf.record(&self.ex);
+            }
+            if let Some(cv) = &mut self.cv {
+                cv.record(&self.ex);
+            }
+        }
+
+        Ok(())
     }
 
-    // Run the machine
-    pub fn run(&mut self) {
-        while self.os && !self.ps {
-            let opcode = self.fetch_inst();
-            match opcode {
-                1  => self.add(),
-                2  => self.mul(),
-                3  => self.inp(),
-                4  => self.out(),
-                5  => self.jnz(),
-                6  => self.jz(),
-                7  => self.lt(),
-                8  => self.eq(),
-                9  => self.rbx(),
-                99 => self.hcf(),
-                _  => panic!("Invalid opcode"),
+    // Fetch and execute a single instruction
+    fn cycle(&mut self) -> Result<(), VmError> {
+        let opcode = self.fetch_inst();
+        match opcode {
+            1  => self.add(),
+            2  => self.mul(),
+            3  => self.inp(),
+            4  => self.out(),
+            5  => self.jnz(),
+            6  => self.jz(),
+            7  => self.lt(),
+            8  => self.eq(),
+            9  => self.rbx(),
+            99 => {
+                self.hcf();
+                Ok(())
+            }
+            _  => {
+                let (ip, word) = (self.ip, self.peek(self.ip));
+                Err(VmError::InvalidOpcode { ip, word, opcode })
             }
         }
     }
 
     // ////////////////////////////////////////////////////////////////////////
-    // Dump the output queue
-    pub fn dump_output(&self) -> &Vec<isize> {
+    // Dump the output queue, i.e. all output not yet consumed
+    pub fn dump_output(&self) -> &VecDeque<Word> {
         &self.oq
     }
 
+    // Takes all pending output, leaving the output queue empty
+    pub fn take_output(&mut self) -> Vec<Word> {
+        std::mem::take(&mut self.oq).into()
+    }
+
+    // Consumes the oldest pending output
+    pub fn pop_output(&mut self) -> Option<Word> {
+        self.oq.pop_front()
+    }
+
+    // Number of pending outputs
+    pub fn output_len(&self) -> usize {
+        self.oq.len()
+    }
+
+    // Consumes the pending output in order
+    pub fn drain_output(&mut self) -> std::collections::vec_deque::Drain<'_, Word> {
+        self.oq.drain(..)
+    }
+
+    // Bounds the output queue. Opcode 4 then blocks with OutputFull while the
+    // queue holds `limit` values, until some are consumed. None removes the bound.
+    pub fn limit_output(&mut self, limit: Option<usize>) {
+        self.ol = limit;
+    }
+
+    // Dump the allocated memory as (start address, cells) stretches, see Memory::pages
+    pub fn dump_memory(&self) -> Vec<(usize, &[Word])> {
+        self.cs.pages()
+    }
+
+    // The memory and its backend
+    pub fn memory(&self) -> &Memory {
+        &self.cs
+    }
+
+    // Bounds the addresses a program may use, anything at or above the limit
+    // faults with MemoryLimit. None removes the bound.
+    pub fn limit_memory(&mut self, limit: Option<usize>) {
+        self.cs.set_limit(limit);
+    }
+
+    // Gives the machine a fuel budget, replacing what was left of the last
+    // one. A machine that ran out of fuel continues on the next run.
+    pub fn refuel(&mut self, budget: Budget) {
+        self.fu.set(budget);
+    }
+
+    // Instructions left under an instruction budget, None for other budgets
+    pub fn fuel_left(&self) -> Option<u64> {
+        self.fu.left()
+    }
+
+    // Instructions executed since the last reboot
+    pub fn counters(&self) -> &Counters {
+        &self.ct
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.ct = Counters::default();
+    }
+
+    // Sets how overflowing arithmetic is handled, it wraps by default
+    pub fn set_arithmetic(&mut self, mode: Arithmetic) {
+        self.am = mode;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.am
+    }
+
+    // Turns the decoded instruction cache on or off, it is on by default
+    pub fn cache_decoding(&mut self, enabled: bool) {
+        self.dc.set_enabled(enabled);
+    }
+
     // Fetch the next instruction
-    fn fetch_inst(&mut self) -> isize {
-        let mut code = self.cs[self.ip];
-        let opcode = code % 100;
-        code /= 100;
+    fn fetch_inst(&mut self) -> Word {
+        let (word, opcode, modes) = self.dc.fetch(self.ip, &self.cs);
+        self.pm = modes;
 
-        for idx in 0..3 {
-            self.pm[idx] = code % 10;
-            code /= 10;
+        let arity = OpInfo::lookup(opcode).map_or(0, |info| info.arity);
+        let mut operands = [Operand::default(); 3];
+        for (idx, op) in operands.iter_mut().enumerate().take(arity) {
+            op.mode = self.pm[idx];
+            op.raw = self.peek(self.ip + idx + 1);
         }
 
+        self.ex = Step {
+            ip: self.ip,
+            word,
+            opcode,
+            modes: self.pm,
+            arity,
+            operands,
+            ..Step::default()
+        };
+
         opcode
     }
 
     // Gets the address from memory
-    fn get_addr(&mut self, offset: usize) -> usize {
+    fn get_addr(&mut self, offset: usize) -> Result<usize, VmError> {
+        let raw = self.ex.operands[offset - 1].raw;
+        self.ex.read = self.ex.read.max(offset);
+
         let addr = match self.pm[offset - 1] {
-            0 => self.cs[self.ip + offset] as usize,
-            1 => self.ip + offset,
-            2 => (self.rb + self.cs[self.ip + offset]) as usize,
-            _ => unreachable!(),
+            0 => raw,
+            1 => (self.ip + offset) as Word,
+            2 => self.arith(self.rb.overflowing_add(raw))?,
+            mode => {
+                let (ip, word) = (self.ip, self.peek(self.ip));
+                return Err(VmError::InvalidMode { ip, word, opcode: word % 100, param: offset, mode });
+            }
         };
 
-        if addr >= self.cs.len() {
-            self.cs.resize(addr + 1, 0);
-        };
+        if addr < 0 {
+            let (ip, word) = (self.ip, self.peek(self.ip));
+            let mode = self.pm[offset - 1];
+            return Err(VmError::NegativeAddress { ip, word, opcode: word % 100, param: offset, mode, addr });
+        }
+        // Addresses that do not fit in a usize, possible with wide words, are past any limit
+        let addr = usize::try_from(addr).unwrap_or(usize::MAX);
+        if addr == usize::MAX || !self.cs.in_bounds(addr) {
+            let (ip, word) = (self.ip, self.peek(self.ip));
+            return Err(VmError::MemoryLimit { ip, word, opcode: word % 100, param: offset, addr });
+        }
+
+        if self.pm[offset - 1] != 1 {
+            self.ex.operands[offset - 1].addr = Some(addr);
+        }
 
-        addr
+        Ok(addr)
     }
-    
+
+    // Gets the address for a parameter that is written to
+    fn put_addr(&mut self, offset: usize) -> Result<usize, VmError> {
+        if self.pm[offset - 1] == 1 {
+            let (ip, word) = (self.ip, self.peek(self.ip));
+            return Err(VmError::WriteInImmediateMode { ip, word, opcode: word % 100, param: offset });
+        }
+
+        self.get_addr(offset)
+    }
+
     // Fetches a parameter for an operation according to parameter mode
-    fn get_param(&mut self, offset: usize) -> isize {
-        let addr = self.get_addr(offset);
-        self.cs[addr]
+    fn get_param(&mut self, offset: usize) -> Result<Word, VmError> {
+        let addr = self.get_addr(offset)?;
+        let value = if self.pm[offset - 1] == 1 {
+            self.ex.operands[offset - 1].raw
+        } else {
+            self.read_mem(addr)
+        };
+        self.ex.operands[offset - 1].value = value;
+        Ok(value)
+    }
+
+    // Writes a value to the destination parameter at the given offset
+    fn put(&mut self, offset: usize, addr: usize, value: Word) {
+        self.write_mem(addr, value);
+        self.ex.operands[offset - 1].value = value;
+        self.ex.write = Some((addr, value));
+    }
+
+    // Reads a data cell for an instruction. All data reads go through here.
+    pub(crate) fn read_mem(&mut self, addr: usize) -> Word {
+        let value = self.cs.read(addr);
+        if self.pb.has_watches() {
+            self.pb.check_watch(self.ip, addr, Access::Read, value);
+        }
+        value
+    }
+
+    // Writes a data cell for an instruction. All data writes go through here.
+    pub(crate) fn write_mem(&mut self, addr: usize, value: Word) {
+        self.cs.write(addr, value);
+        self.dc.invalidate(addr);
+        if self.pb.has_watches() {
+            self.pb.check_watch(self.ip, addr, Access::Write, value);
+        }
+    }
+
+    // Next input value, from the input queue and then the input device
+    pub(crate) fn next_input(&mut self) -> Option<Word> {
+        match &mut self.io.input {
+            Some(dev) => self.iq.pop_front().or_else(|| dev.read()),
+            None => self.iq.pop_front(),
+        }
+    }
+
+    // Whether opcode 4 has to wait for space in the bounded output queue
+    pub(crate) fn output_full(&self) -> bool {
+        self.io.output.is_none() && self.ol.is_some_and(|limit| self.oq.len() >= limit)
+    }
+
+    // Sends a value to the output device or queue
+    pub(crate) fn emit(&mut self, value: Word) {
+        match &mut self.io.output {
+            Some(dev) => dev.write(value),
+            None => self.oq.push_back(value),
+        }
+    }
+
+    // Moves the registers and state, for code executing outside the interpreter
+    pub(crate) fn set_regs(&mut self, ip: usize, rb: Word, st: MachineState) {
+        self.ip = ip;
+        self.rb = rb;
+        self.st = st;
+    }
+
+    // Record of the last instruction executed
+    pub(crate) fn last_step(&self) -> &Step {
+        &self.ex
+    }
+
+    // Whether execution has to go through the interpreter instruction by
+    // instruction, for probes, a trace, a profile, coverage, a fuel budget or
+    // checked arithmetic
+    pub(crate) fn is_observed(&self) -> bool {
+        self.pb.has_breaks()
+            || self.pb.has_watches()
+            || self.tr.is_some()
+            || self.pf.is_some()
+            || self.cv.is_some()
+            || self.fu.is_limited()
+            || self.am == Arithmetic::Checked
+    }
+
+    // Counts an instruction completed outside the interpreter
+    pub(crate) fn count(&mut self, opcode: Word) {
+        self.ct.record(opcode);
+    }
+
+    // Reads memory without growing it; unallocated memory reads as zero
+    fn peek(&self, addr: usize) -> Word {
+        self.cs.get(addr)
+    }
+
+    // Result of an overflowing operation, a fault if it overflowed in checked mode
+    fn arith(&self, (value, overflow): (Word, bool)) -> Result<Word, VmError> {
+        if overflow && self.am == Arithmetic::Checked {
+            let (ip, word) = (self.ip, self.peek(self.ip));
+            return Err(VmError::ArithmeticOverflow { ip, word, opcode: word % 100 });
+        }
+        Ok(value)
+    }
+
+    // Fetches a jump target, which must not be negative
+    fn get_target(&mut self, offset: usize) -> Result<usize, VmError> {
+        let target = self.get_param(offset)?;
+        if target < 0 {
+            let (ip, word) = (self.ip, self.peek(self.ip));
+            let mode = self.pm[offset - 1];
+            return Err(VmError::NegativeAddress { ip, word, opcode: word % 100, param: offset, mode, addr: target });
+        }
+
+        Ok(usize::try_from(target).unwrap_or(usize::MAX))
     }
 
     // Increment the instruction pointer
     fn inc_ptr(&mut self, offset: usize) {
-        if self.ip < self.cs.len() - offset {
+        if self.ip + offset < self.cs.len() {
             self.ip += offset;
         } else {
             self.hcf();
         }
     }
 
-    // Inject a value at a given memory location
-    pub fn inject(&mut self, index: usize, value: isize) {
-        self.cs[index] = value;
+    // Inject a value at a given memory location, growing memory as needed
+    pub fn inject(&mut self, index: usize, value: Word) {
+        self.cs.write(index, value);
+        self.dc.invalidate(index);
+    }
+
+    // Pending inputs
+    pub fn input_queue(&self) -> &VecDeque<Word> {
+        &self.iq
     }
 
     // Extends the input queue
-    pub fn input_ext(&mut self, inputs: &[isize]) {
+    pub fn input_ext(&mut self, inputs: &[Word]) {
         self.iq.extend(inputs.iter());
     }
 
@@ -118,37 +724,68 @@ impl Machine {
         self.iq.extend(other.oq.drain(..));
     }
 
-    // Checks if the machine is still running
+    // Attaches an input device, read by opcode 3 once the input queue is empty
+    pub fn attach_input(&mut self, dev: impl InputPort + Send + 'static) {
+        self.io.input = Some(Box::new(dev));
+    }
+
+    // Attaches an output device, which then receives output instead of the output queue
+    pub fn attach_output(&mut self, dev: impl OutputPort + Send + 'static) {
+        self.io.output = Some(Box::new(dev));
+    }
+
+    // Detaches the input device, if any, and returns it
+    pub fn detach_input(&mut self) -> Option<BoxedInput> {
+        self.io.input.take()
+    }
+
+    // Detaches the output device, if any, and returns it
+    pub fn detach_output(&mut self) -> Option<BoxedOutput> {
+        self.io.output.take()
+    }
+
+    // Replaces the attached devices, returning the old ones. Lets a runtime
+    // put its own devices in for a run and give the caller's back after.
+    pub(crate) fn swap_devices(&mut self, ports: Ports) -> Ports {
+        std::mem::replace(&mut self.io, ports)
+    }
+
+    // Checks if the machine is still running, i.e. has neither halted nor faulted
     pub fn is_running(&self) -> bool {
-        self.os
+        !matches!(self.st, MachineState::Halted | MachineState::Faulted(_))
     }
 
     // Load inputs into queue
-    pub fn load(&mut self, inputs: VecDeque<isize>) {
+    pub fn load(&mut self, inputs: VecDeque<Word>) {
         self.iq = inputs;
     }
 
     // Parses the program
-    pub fn parse(input: &str) -> Program {
+    pub fn parse(input: &str) -> Result<Program, ParseError> {
         input
             .split(',')
-            .map(|line| line.parse().unwrap())
+            .enumerate()
+            .map(|(index, token)| {
+                token.trim().parse().map_err(|_| ParseError { index, token: token.to_string() })
+            })
             .collect()
     }
 
     // Pauses the operation and releases the machine
     pub fn pause(&mut self) {
-        self.ps = true;
+        if self.is_running() {
+            self.st = MachineState::Paused;
+        }
     }
 
-    // Read the value at a given location
-    pub fn read(&self, index: usize) -> isize {
-        self.cs[index]
+    // Read the value at a given location, unallocated memory reads as zero
+    pub fn read(&self, index: usize) -> Word {
+        self.peek(index)
     }
 
-    // Outputs only the last entry of the output
-    pub fn read_last(&self) -> isize {
-        *self.oq.last().unwrap()
+    // Outputs only the last entry of the output, None if there is none
+    pub fn read_last(&self) -> Option<Word> {
+        self.oq.back().copied()
     }
 
     // Displays the output queue
@@ -156,27 +793,187 @@ impl Machine {
         println!("{:?}", self.oq);
     }
 
-    // Resets the machine and loads a program 
+    // Resets the machine and loads a program, keeping the memory backend, the
+    // limits and the fuel budget
     pub fn reboot(&mut self, prog: &Program) {
         self.ip = 0;
-        self.cs = prog.clone();
+        self.cs.load(prog);
+        self.dc.reload(prog);
         self.iq.clear();
         self.oq.clear();
         self.pm = [0; 3];
-        self.os = true;
-        self.ps = false;
+        self.st = MachineState::Running;
         self.rb = 0;
+        self.ex = Step::default();
+        self.ct = Counters::default();
+        if let Some(tr) = &mut self.tr {
+            *tr = Trace::new();
+        }
+        if let Some(pf) = &mut self.pf {
+            *pf = Profile::new();
+        }
+        if let Some(cv) = &mut self.cv {
+            *cv = Coverage::new();
+        }
     }
 
     // Resumes operation
-    pub fn resume(&mut self) {
-        self.ps = false;
-        self.run();
+    pub fn resume(&mut self) -> Result<MachineState, VmError> {
+        if self.st == MachineState::Paused {
+            self.st = MachineState::Running;
+        }
+        self.run()
+    }
+
+    // Current state of the machine
+    pub fn state(&self) -> MachineState {
+        self.st
+    }
+
+    // Sets a breakpoint before the instruction at an address, returns its id
+    pub fn break_at(&mut self, addr: usize) -> usize {
+        self.pb.break_at(addr)
+    }
+
+    // Sets a breakpoint that fires before any instruction when the condition holds
+    pub fn break_if<F: Fn(&Machine) -> bool + Send + Sync + 'static>(&mut self, cond: F) -> usize {
+        self.pb.break_if(Arc::new(cond))
+    }
+
+    // Sets a watchpoint on a memory cell, returns its id
+    pub fn watch(&mut self, addr: usize, kind: WatchKind) -> usize {
+        self.pb.watch(addr, kind)
+    }
+
+    // Removes a breakpoint or watchpoint by id
+    pub fn unset(&mut self, id: usize) -> bool {
+        self.pb.remove(id)
+    }
+
+    // Removes all breakpoints and watchpoints
+    pub fn unset_all(&mut self) {
+        self.pb.clear();
+    }
+
+    // Instruction breakpoints as (id, address)
+    pub fn breakpoints(&self) -> Vec<(usize, usize)> {
+        self.pb.break_addrs()
+    }
+
+    // Watchpoints as (id, address, kind)
+    pub fn watchpoints(&self) -> Vec<(usize, usize, WatchKind)> {
+        self.pb.watch_list()
+    }
+
+    // Current instruction pointer
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    // Current relative base
+    pub fn rb(&self) -> Word {
+        self.rb
+    }
+
+    // Captures the complete state of the machine
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ip: self.ip,
+            rb: self.rb,
+            state: self.st,
+            backend: self.cs.backend(),
+            memory_limit: self.cs.limit(),
+            memory_len: self.cs.len(),
+            memory: self
+                .cs
+                .pages()
+                .into_iter()
+                .flat_map(|(start, cells)| cells.iter().enumerate().map(move |(idx, &value)| (start + idx, value)))
+                .filter(|&(_, value)| value != 0)
+                .collect(),
+            input: self.iq.iter().copied().collect(),
+            output: self.oq.iter().copied().collect(),
+        }
+    }
+
+    // Restores the machine from a snapshot, keeping its trace, breakpoints and
+    // watchpoints. The memory backend and limit come from the snapshot, except
+    // that dense memory reaching past the limit, or past DEFAULT_MEMORY_LIMIT
+    // without one, is restored paged so a snapshot cannot exhaust the host.
+    pub fn restore(&mut self, snap: &Snapshot) {
+        self.ip = snap.ip;
+        self.rb = snap.rb;
+        self.st = snap.state;
+        let backend = if snap.memory_len > snap.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT) {
+            Backend::Paged
+        } else {
+            snap.backend
+        };
+        self.cs = Memory::new(backend, &[]).with_limit(snap.memory_limit);
+        self.cs.restore(snap.memory_len, snap.memory.iter().map(|(&addr, &value)| (addr, value)));
+        self.dc.clear();
+        self.iq = snap.input.iter().copied().collect();
+        self.oq = snap.output.iter().copied().collect();
+        self.pm = [0; 3];
+        self.ex = Step::default();
+    }
+
+    // Creates a machine from a snapshot
+    pub fn from_snapshot(snap: &Snapshot) -> Self {
+        let mut vm = Self::new(&Vec::new());
+        vm.restore(snap);
+        vm
+    }
+
+    // Starts recording every executed instruction
+    pub fn start_trace(&mut self) {
+        self.tr = Some(Trace::new());
+    }
+
+    // Stops recording and hands over the trace
+    pub fn stop_trace(&mut self) -> Option<Trace> {
+        self.tr.take()
+    }
+
+    // Trace recorded so far, if tracing
+    pub fn trace(&self) -> Option<&Trace> {
+        self.tr.as_ref()
+    }
+
+    // Starts counting executions per address and opcode, and timing the
+    // stretches between input waits
+    pub fn start_profile(&mut self) {
+        self.pf = Some(Profile::new());
+    }
+
+    // Stops profiling and hands over the profile
+    pub fn stop_profile(&mut self) -> Option<Profile> {
+        self.pf.take()
+    }
+
+    // Profile collected so far, if profiling
+    pub fn profile(&self) -> Option<&Profile> {
+        self.pf.as_ref()
+    }
+
+    // Starts marking the addresses executed, read and written
+    pub fn start_coverage(&mut self) {
+        self.cv = Some(Coverage::new());
+    }
+
+    // Stops collecting coverage and hands it over
+    pub fn stop_coverage(&mut self) -> Option<Coverage> {
+        self.cv.take()
+    }
+
+    // Coverage collected so far, if collecting
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.cv.as_ref()
     }
 
     // SHOW content of memory location
     pub fn show(&self, pos: usize) {
-        println!("{}", self.cs[pos]);
+        println!("{}", self.cs.get(pos));
     }
 
     // *** All the opcode shit ***
@@ -189,86 +986,367 @@ impl Machine {
     // Parameters that an instruction writes to will never be in immediate mode.
 
     // Opcode 1 - ADD values from indices A and B, place into index C
-    fn add(&mut self,) {
-        let addr = self.get_addr(3);
-        self.cs[addr] = self.get_param(1) + self.get_param(2);
+    fn add(&mut self) -> Result<(), VmError> {
+        let addr = self.put_addr(3)?;
+        let value = self.get_param(1)?.overflowing_add(self.get_param(2)?);
+        let value = self.arith(value)?;
+        self.put(3, addr, value);
         self.inc_ptr(4);
+        Ok(())
     }
 
     // Opcode 2 - MULTIPLY values from indices A and B, place into index C
-    fn mul(&mut self) {
-        let addr = self.get_addr(3);
-        self.cs[addr] = self.get_param(1) * self.get_param(2);
+    fn mul(&mut self) -> Result<(), VmError> {
+        let addr = self.put_addr(3)?;
+        let value = self.get_param(1)?.overflowing_mul(self.get_param(2)?);
+        let value = self.arith(value)?;
+        self.put(3, addr, value);
         self.inc_ptr(4);
+        Ok(())
     }
 
     // Opcode 3 - Takes an INPUT value, and stores it at address X
-    fn inp(&mut self) {
-        if let Some(inst) = self.iq.pop_front() {
-            let addr = self.get_addr(1);
-            self.cs[addr] = inst;
+    fn inp(&mut self) -> Result<(), VmError> {
+        // A faulting destination must not consume the input
+        let addr = self.put_addr(1)?;
+        if let Some(inst) = self.next_input() {
+            self.put(1, addr, inst);
+            self.ex.input = Some(inst);
             self.inc_ptr(2);
         } else {
-            self.pause();
+            self.st = MachineState::AwaitingInput;
         }
+        Ok(())
     }
 
     // Opcode 4 - OUTPUTS a value from address X
-    fn out(&mut self) {
-        let output = self.get_param(1);
-        self.oq.push(output);
+    fn out(&mut self) -> Result<(), VmError> {
+        if self.output_full() {
+            self.st = MachineState::OutputFull;
+            return Ok(());
+        }
+
+        let output = self.get_param(1)?;
+        self.emit(output);
+        self.ex.output = Some(output);
         self.inc_ptr(2);
+        Ok(())
     }
 
     // Opcode 5 - JUMP-IF-TRUE, if the value in A is non-zero, sets the instruction pointer to value B
-    fn jnz(&mut self) {
-        if self.get_param(1) != 0 {
-            self.ip = self.get_param(2) as usize;
+    fn jnz(&mut self) -> Result<(), VmError> {
+        if self.get_param(1)? != 0 {
+            self.ip = self.get_target(2)?;
         } else {
             self.inc_ptr(3);
         }
+        Ok(())
     }
 
     // Opcode 6 - JUMP-IF-FALSE, if a value in A is zero, sets the instruction pointer to value B
-    fn jz(&mut self) {
-        if self.get_param(1) == 0 {
-            self.ip = self.get_param(2) as usize;
+    fn jz(&mut self) -> Result<(), VmError> {
+        if self.get_param(1)? == 0 {
+            self.ip = self.get_target(2)?;
         } else {
             self.inc_ptr(3);
         }
+        Ok(())
     }
 
     // Opcode 7 - Tests if value A is LESS THAN value B, and puts the truth in value C
-    fn lt(&mut self) {
-        let addr = self.get_addr(3);
-        self.cs[addr] = if self.get_param(1) < self.get_param(2) {
+    fn lt(&mut self) -> Result<(), VmError> {
+        let addr = self.put_addr(3)?;
+        let value = if self.get_param(1)? < self.get_param(2)? {
             1
         } else {
             0
         };
+        self.put(3, addr, value);
         self.inc_ptr(4);
+        Ok(())
     }
 
     // Opcode 8 - Tests if value A is EQUAL to value B, and puts the truth in value C
-    fn eq(&mut self) {
-        let addr = self.get_addr(3);
-        self.cs[addr] = if self.get_param(1) == self.get_param(2) {
+    fn eq(&mut self) -> Result<(), VmError> {
+        let addr = self.put_addr(3)?;
+        let value = if self.get_param(1)? == self.get_param(2)? {
             1
         } else {
             0
         };
+        self.put(3, addr, value);
         self.inc_ptr(4);
+        Ok(())
     }
 
     // Opcode 9 - Adjusts the relative base by an offset
-    fn rbx(&mut self) {
-        self.rb += self.get_param(1);
+    fn rbx(&mut self) -> Result<(), VmError> {
+        let rb = self.rb.overflowing_add(self.get_param(1)?);
+        self.rb = self.arith(rb)?;
         self.inc_ptr(2);
+        Ok(())
     }
 
     // Opcode 99 - Halt and Catch Fire
     fn hcf(&mut self) {
-        self.os = false;
-        self.ps = true;
+        self.st = MachineState::Halted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Access, Trap, WatchKind};
+
+    #[test]
+    fn fault_invalid_opcode() {
+        let mut vm = Machine::new(&vec![1101,1,1,5,42,0]);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode { ip: 4, word: 42, opcode: 42 }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn fault_invalid_mode() {
+        let mut vm = Machine::new(&vec![304,0,99]);
+        assert_eq!(vm.run(), Err(VmError::InvalidMode { ip: 0, word: 304, opcode: 4, param: 1, mode: 3 }));
+    }
+
+    #[test]
+    fn fault_keeps_input() {
+        let mut vm = Machine::new(&vec![103,0,99]);
+        vm.input_ext(&[7]);
+        assert_eq!(vm.run(), Err(VmError::WriteInImmediateMode { ip: 0, word: 103, opcode: 3, param: 1 }));
+        assert_eq!(vm.input_queue(), &[7]);
+    }
+
+    #[test]
+    fn fault_negative_address() {
+        let mut vm = Machine::new(&vec![204,-1,99]);
+        assert_eq!(
+            vm.run(),
+            Err(VmError::NegativeAddress { ip: 0, word: 204, opcode: 4, param: 1, mode: 2, addr: -1 })
+        );
+    }
+
+    #[test]
+    fn fault_write_in_immediate_mode() {
+        let mut vm = Machine::new(&vec![11101,1,1,0,99]);
+        assert_eq!(vm.run(), Err(VmError::WriteInImmediateMode { ip: 0, word: 11101, opcode: 1, param: 3 }));
+    }
+
+    #[test]
+    fn fault_memory_limit() {
+        let prog = vec![1101,1,1,1_000_000_000_000,99];
+        let mut vm = Machine::new(&prog);
+        assert_eq!(vm.run(), Err(VmError::MemoryLimit { ip: 0, word: 1101, opcode: 1, param: 3, addr: 1_000_000_000_000 }));
+
+        let mut vm = Machine::with_memory(Memory::paged(&prog));
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        assert_eq!(vm.read(1_000_000_000_000), 2);
+        assert_eq!(vm.memory().allocated(), 2048);
+    }
+
+    #[test]
+    fn fault_arithmetic_overflow() {
+        let prog = vec![1101,Word::MAX,1,7,4,7,99,0];
+        let mut vm = Machine::new(&prog);
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        assert_eq!(vm.dump_output(), &vec![Word::MIN]);
+
+        let mut vm = Machine::new(&prog);
+        vm.set_arithmetic(Arithmetic::Checked);
+        assert_eq!(vm.run(), Err(VmError::ArithmeticOverflow { ip: 0, word: 1101, opcode: 1 }));
+        assert_eq!(vm.read(7), 0);
+    }
+
+    #[test]
+    fn state_awaiting_input_then_halted() {
+        let mut vm = Machine::new(&vec![3,5,4,5,99,0]);
+        assert_eq!(vm.run(), Ok(MachineState::AwaitingInput));
+        vm.input_ext(&[7]);
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        assert_eq!(vm.dump_output(), &vec![7]);
+    }
+
+    #[test]
+    fn state_paused_by_host() {
+        let mut vm = Machine::new(&vec![104,1,99]);
+        vm.pause();
+        assert_eq!(vm.run(), Ok(MachineState::Paused));
+        assert_eq!(vm.resume(), Ok(MachineState::Halted));
+    }
+
+    #[test]
+    fn state_faulted_sticks() {
+        let mut vm = Machine::new(&vec![42]);
+        let err = vm.run().unwrap_err();
+        assert_eq!(vm.state(), MachineState::Faulted(err));
+        assert_eq!(vm.resume(), Err(err));
+    }
+
+    #[test]
+    fn step_reports_execution() {
+        let mut vm = Machine::new(&vec![1001,5,-3,5,99,10]);
+        let step = vm.step().unwrap().unwrap();
+        assert_eq!(step.opcode, 1);
+        assert_eq!(step.modes, [0, 1, 0]);
+        assert_eq!(step.operands()[0], Operand { mode: 0, raw: 5, addr: Some(5), value: 10 });
+        assert_eq!(step.operands()[1], Operand { mode: 1, raw: -3, addr: None, value: -3 });
+        assert_eq!(step.write, Some((5, 7)));
+        assert_eq!(step.next_ip, 4);
+        assert_eq!(vm.step().unwrap().unwrap().opcode, 99);
+        assert_eq!(vm.step(), Ok(None));
+    }
+
+    #[test]
+    fn step_of_jump_not_taken() {
+        let mut vm = Machine::new(&vec![1105,0,7,99]);
+        let step = vm.step().unwrap().unwrap();
+        assert_eq!((step.arity, step.read), (2, 1));
+        assert_eq!(step.operands(), &[Operand { mode: 1, raw: 0, addr: None, value: 0 }]);
+        assert_eq!(step.words(), vec![1105, 0, 7]);
+        assert_eq!(step.next_ip, step.ip + step.arity + 1);
+    }
+
+    #[test]
+    fn run_until_output_stops_per_value() {
+        let mut vm = Machine::new(&vec![104,1,104,2,99]);
+        assert_eq!(vm.run_until_output(), Ok(MachineState::OutputReady));
+        assert_eq!(vm.dump_output(), &vec![1]);
+        assert_eq!(vm.run_until_output(), Ok(MachineState::OutputReady));
+        assert_eq!(vm.run_until_output(), Ok(MachineState::Halted));
+        assert_eq!(vm.dump_output(), &vec![1, 2]);
+    }
+
+    #[test]
+    fn output_consumption_and_limit() {
+        let mut vm = Machine::new(&vec![104,1,104,2,104,3,104,4,99]);
+        vm.limit_output(Some(2));
+        assert_eq!(vm.run(), Ok(MachineState::OutputFull));
+        assert_eq!(vm.ip(), 4);
+        assert_eq!(vm.pop_output(), Some(1));
+        assert_eq!(vm.run(), Ok(MachineState::OutputFull));
+        assert_eq!(vm.output_len(), 2);
+        assert_eq!(vm.drain_output().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        assert_eq!(vm.read_last(), Some(4));
+        assert_eq!(vm.take_output(), vec![4]);
+        assert_eq!(vm.read_last(), None);
+    }
+
+    #[test]
+    fn fuel_budget_stops_and_resumes() {
+        // Counts 0 to 9 at address 14, then loops forever
+        let mut vm = Machine::new(&vec![1001,14,1,14,1007,14,10,15,1005,15,0,1105,1,11,0,0]);
+        vm.refuel(Budget::Instructions(10));
+        assert_eq!(vm.run(), Ok(MachineState::OutOfFuel));
+        assert_eq!((vm.read(14), vm.ip(), vm.fuel_left()), (4, 4, Some(0)));
+        assert_eq!(vm.step(), Ok(None));
+
+        vm.refuel(Budget::Instructions(100));
+        assert_eq!(vm.run(), Ok(MachineState::OutOfFuel));
+        assert_eq!(vm.read(14), 10);
+        assert_eq!(vm.counters().instructions(), 110);
+        assert_eq!(vm.counters().count(1), 10);
+        assert_eq!(vm.counters().iter().map(|(opcode, _)| opcode).collect::<Vec<_>>(), vec![1, 5, 7]);
+
+        vm.refuel(Budget::Time(std::time::Duration::ZERO));
+        assert_eq!(vm.run(), Ok(MachineState::OutOfFuel));
+        assert_eq!(vm.counters().instructions(), 110);
+        vm.reboot(&vec![99]);
+        vm.refuel(Budget::Unlimited);
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        assert_eq!(vm.counters().instructions(), 1);
+    }
+
+    #[test]
+    fn decode_cache_sees_self_modification() {
+        let prog = crate::utils::assemble("
+            start: ADD #2, #3, [r]
+                   OUT [r]
+                   JNZ [done], #end
+                   ADD #1, #0, [done]
+                   ADD #1102, #0, [start]
+                   JZ  #0, #start
+            end:   HLT
+            r:     data 0
+            done:  data 0
+        ").unwrap();
+        let mut vm = Machine::new(&prog);
+        vm.run().unwrap();
+        assert_eq!(vm.take_output(), vec![5, 6]);
+
+        vm.reboot(&prog);
+        vm.run().unwrap();
+        assert_eq!(vm.take_output(), vec![5, 6]);
+    }
+
+    #[test]
+    fn decode_cache_sees_host_writes() {
+        // OUT #7, HLT
+        let prog = vec![104,7,99];
+        let mut vm = Machine::new(&prog);
+        vm.run().unwrap();
+
+        // An injected instruction word replaces the cached one, OUT [2] prints 99 where OUT #2 would print 2
+        vm.reboot(&prog);
+        vm.inject(0, 4);
+        vm.inject(1, 2);
+        vm.run().unwrap();
+        assert_eq!(vm.take_output(), vec![99]);
+
+        // So does a different program loaded over the same addresses
+        vm.reboot(&vec![1101,2,2,5,4,5,99]);
+        vm.run().unwrap();
+        assert_eq!(vm.take_output(), vec![4]);
+
+        // And memory restored from a snapshot
+        let snap = Machine::new(&vec![104,9,99]).snapshot();
+        vm.restore(&snap);
+        vm.run().unwrap();
+        assert_eq!(vm.take_output(), vec![9]);
+    }
+
+    #[test]
+    fn run_for_and_run_until() {
+        let mut vm = Machine::new(&vec![1001,12,1,12,1007,12,5,13,1005,13,0,99,0,0]);
+        assert_eq!(vm.run_for(1), Ok(MachineState::Running));
+        assert_eq!(vm.read(12), 1);
+        assert_eq!(vm.run_until(|m| m.read(12) == 3), Ok(MachineState::Running));
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+        assert_eq!(vm.read(12), 5);
+    }
+
+    #[test]
+    fn breakpoints_stop_before_and_continue() {
+        let mut vm = Machine::new(&vec![104,1,104,2,104,3,99]);
+        let id = vm.break_at(2);
+        assert_eq!(vm.run(), Ok(MachineState::Break(Trap::Breakpoint { id, ip: 2 })));
+        assert_eq!(vm.dump_output(), &vec![1]);
+        vm.break_if(|m| m.dump_output().len() == 3);
+        let trap = vm.run().unwrap();
+        assert!(matches!(trap, MachineState::Break(Trap::Breakpoint { ip: 6, .. })));
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+    }
+
+    #[test]
+    fn watchpoints_report_access() {
+        let mut vm = Machine::new(&vec![1001,9,1,9,4,9,99,0,0,41]);
+        let id = vm.watch(9, WatchKind::Write);
+        assert_eq!(
+            vm.run(),
+            Ok(MachineState::Break(Trap::Watchpoint { id, ip: 0, addr: 9, access: Access::Write, value: 42 }))
+        );
+        vm.unset(id);
+        let id = vm.watch(9, WatchKind::Read);
+        assert_eq!(
+            vm.run(),
+            Ok(MachineState::Break(Trap::Watchpoint { id, ip: 4, addr: 9, access: Access::Read, value: 42 }))
+        );
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+    }
+
+    #[test]
+    fn parse_reports_bad_token() {
+        assert_eq!(Machine::parse("1,2,x3,99"), Err(ParseError { index: 2, token: "x3".to_string() }));
+    }
+}
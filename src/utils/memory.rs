@@ -0,0 +1,215 @@
+use std::{borrow::Cow, collections::HashMap};
+use super::intcode::Word;
+
+// Cells per page of paged memory
+pub(crate) const PAGE: usize = 1024;
+
+// How a machine stores its memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Dense, // One vector, grown up to the highest address touched
+    Paged, // Fixed-size pages, allocated the first time one is written
+}
+
+#[derive(Debug, Clone)]
+enum Cells {
+    Dense(Vec<Word>),
+    Paged(HashMap<usize, Box<[Word; PAGE]>>),
+}
+
+// Memory of a machine. Unallocated cells read as zero. The length is one past
+// the highest address touched, as with the dense backend, so both backends
+// behave the same. An optional limit bounds the addresses a program may use,
+// the machine faults with MemoryLimit on any address at or above it.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    cells: Cells,
+    len: usize,
+    limit: Option<usize>,
+}
+
+impl Memory {
+    pub fn new(backend: Backend, prog: &[Word]) -> Self {
+        let cells = match backend {
+            Backend::Dense => Cells::Dense(Vec::new()),
+            Backend::Paged => Cells::Paged(HashMap::new()),
+        };
+        let mut mem = Self { cells, len: 0, limit: None };
+        mem.load(prog);
+        mem
+    }
+
+    pub fn dense(prog: &[Word]) -> Self {
+        Self::new(Backend::Dense, prog)
+    }
+
+    pub fn paged(prog: &[Word]) -> Self {
+        Self::new(Backend::Paged, prog)
+    }
+
+    // Sets the memory limit, builder style
+    pub fn with_limit(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.cells {
+            Cells::Dense(_) => Backend::Dense,
+            Cells::Paged(_) => Backend::Paged,
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    // Whether an address is below the limit
+    pub fn in_bounds(&self, addr: usize) -> bool {
+        self.limit.is_none_or(|limit| addr < limit)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Replaces the contents with a program, keeping the backend and limit
+    pub fn load(&mut self, prog: &[Word]) {
+        match &mut self.cells {
+            Cells::Dense(cells) => *cells = prog.to_vec(),
+            Cells::Paged(pages) => {
+                pages.clear();
+                for (idx, chunk) in prog.chunks(PAGE).enumerate() {
+                    let mut page = Box::new([0; PAGE]);
+                    page[..chunk.len()].copy_from_slice(chunk);
+                    pages.insert(idx, page);
+                }
+            }
+        }
+        self.len = prog.len();
+    }
+
+    // Reads a cell without touching it
+    pub fn get(&self, addr: usize) -> Word {
+        match &self.cells {
+            Cells::Dense(cells) => cells.get(addr).copied().unwrap_or(0),
+            Cells::Paged(pages) => pages.get(&(addr / PAGE)).map_or(0, |page| page[addr % PAGE]),
+        }
+    }
+
+    // Reads a cell, extending the length to cover it
+    pub fn read(&mut self, addr: usize) -> Word {
+        self.touch(addr);
+        self.get(addr)
+    }
+
+    // Writes a cell, allocating as needed
+    pub fn write(&mut self, addr: usize, value: Word) {
+        self.touch(addr);
+        match &mut self.cells {
+            Cells::Dense(cells) => cells[addr] = value,
+            Cells::Paged(pages) => {
+                // Zero pages stay unallocated
+                if value != 0 || pages.contains_key(&(addr / PAGE)) {
+                    pages.entry(addr / PAGE).or_insert_with(|| Box::new([0; PAGE]))[addr % PAGE] = value;
+                }
+            }
+        }
+    }
+
+    // Number of cells actually allocated
+    pub fn allocated(&self) -> usize {
+        match &self.cells {
+            Cells::Dense(cells) => cells.len(),
+            Cells::Paged(pages) => pages.len() * PAGE,
+        }
+    }
+
+    // Allocated stretches as (start address, cells) in address order, cut at
+    // the length. Unlisted cells are zero.
+    pub fn pages(&self) -> Vec<(usize, &[Word])> {
+        match &self.cells {
+            Cells::Dense(cells) => vec![(0, &cells[..])],
+            Cells::Paged(pages) => {
+                let mut list: Vec<(usize, &[Word])> = pages
+                    .iter()
+                    .filter(|&(&idx, _)| idx * PAGE < self.len)
+                    .map(|(&idx, page)| (idx * PAGE, &page[..PAGE.min(self.len - idx * PAGE)]))
+                    .collect();
+                list.sort_unstable_by_key(|&(addr, _)| addr);
+                list
+            }
+        }
+    }
+
+    // Contents from address 0 up to the first unallocated page or the length,
+    // where code normally lives. Borrowed when the backend is dense.
+    pub fn prefix(&self) -> Cow<'_, [Word]> {
+        match &self.cells {
+            Cells::Dense(cells) => Cow::Borrowed(cells),
+            Cells::Paged(_) => {
+                let mut cells = Vec::new();
+                for (addr, page) in self.pages() {
+                    if addr != cells.len() {
+                        break;
+                    }
+                    cells.extend_from_slice(page);
+                }
+                Cow::Owned(cells)
+            }
+        }
+    }
+
+    // Replaces the contents with the given cells and length, keeping the
+    // backend and limit
+    pub fn restore(&mut self, len: usize, cells: impl IntoIterator<Item = (usize, Word)>) {
+        self.load(&[]);
+        for (addr, value) in cells {
+            self.write(addr, value);
+        }
+        self.len = self.len.max(len);
+    }
+
+    fn touch(&mut self, addr: usize) {
+        self.len = self.len.max(addr + 1);
+        // A restored dense memory may be shorter than its length
+        if let Cells::Dense(cells) = &mut self.cells
+            && addr >= cells.len()
+        {
+            cells.resize(addr + 1, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paged_allocates_touched_pages_only() {
+        let mut mem = Memory::paged(&[1, 2, 3]);
+        mem.write(5_000_000_000, 7);
+        assert_eq!(mem.allocated(), 2 * PAGE);
+        assert_eq!(mem.len(), 5_000_000_001);
+        assert_eq!(mem.read(9_000), 0);
+        assert_eq!(mem.get(5_000_000_000), 7);
+        assert_eq!(mem.allocated(), 2 * PAGE);
+        let starts: Vec<usize> = mem.pages().iter().map(|&(addr, _)| addr).collect();
+        assert_eq!(starts, vec![0, 5_000_000_000 / PAGE * PAGE]);
+        assert_eq!(mem.prefix().len(), PAGE);
+
+        let mut dense = Memory::dense(&[1, 2, 3]);
+        let mut paged = Memory::paged(&[1, 2, 3]);
+        dense.write(2_000, 4);
+        paged.write(2_000, 4);
+        assert_eq!(dense.prefix(), paged.prefix());
+    }
+}
@@ -1,12 +1,50 @@
 #![allow(unused_imports)]
+mod asm;
+mod ascii;
+mod aot;
+mod cache;
+mod codec;
+mod coverage;
+mod decomp;
+mod device;
 mod dirs;
+mod disasm;
+mod flow;
+mod fuel;
+mod future;
 mod grid;
 mod intcode;
 mod macros;
+mod memory;
+mod network;
 mod point;
+mod probe;
+mod profile;
+mod snapshot;
+mod symbolic;
+mod threaded;
+mod trace;
 
+pub use asm::*;
+pub use ascii::*;
+pub use aot::*;
+pub use coverage::*;
+pub use decomp::*;
+pub use device::*;
 pub use dirs::*;
+pub use disasm::*;
+pub use flow::*;
+pub use fuel::*;
+pub use future::*;
 pub use grid::*;
 pub use intcode::*;
 pub use macros::*;
-pub use point::*;
\ No newline at end of file
+pub use memory::*;
+pub use network::*;
+pub use point::*;
+pub use probe::*;
+pub use profile::*;
+pub use snapshot::*;
+pub use symbolic::*;
+pub use threaded::*;
+pub use trace::*;
\ No newline at end of file
@@ -0,0 +1,344 @@
+use std::fmt;
+use super::intcode::{Machine, MachineState, Program, VmError, Word};
+
+// Instructions a machine may run before the scheduler moves on to the next one
+pub(crate) const SLICE: usize = 10_000;
+
+// Where the output of a machine goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Link {
+    Pipe(usize),           // Every value to the input of one machine
+    Broadcast(Vec<usize>), // Every value to the input of each listed machine
+    Packets,               // Groups of three (dest, x, y), x and y to the input of machine dest
+}
+
+// A packet of the Packets link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub dest: Word,
+    pub x: Word,
+    pub y: Word,
+}
+
+// Why the scheduler stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetState {
+    Halted,                                       // Every machine halted
+    Idle,                                         // Every machine is halted or polling with the idle input, nothing is in flight
+    Deadlocked,                                   // Every machine is halted or waiting on input nobody will send
+    Stopped { node: usize, state: MachineState }, // A machine stopped for the host, e.g. at a breakpoint or out of fuel
+}
+
+impl fmt::Display for NetState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetState::Halted => write!(f, "halted"),
+            NetState::Idle => write!(f, "idle"),
+            NetState::Deadlocked => write!(f, "deadlocked"),
+            NetState::Stopped { node, state } => write!(f, "node {node} {state}"),
+        }
+    }
+}
+
+// A machine of the network faulted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeFault {
+    pub node: usize,
+    pub err: VmError,
+}
+
+impl fmt::Display for NodeFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "node {}: {}", self.node, self.err)
+    }
+}
+
+impl std::error::Error for NodeFault {}
+
+// Watches a network from outside, like the NAT of day 23
+pub trait Monitor {
+    // A packet sent to an address with no machine
+    fn receive(&mut self, packet: Packet);
+
+    // The network went idle or deadlocked, returns the packets to wake it up
+    // with. Returning none stops the scheduler.
+    fn wake(&mut self, state: NetState) -> Vec<Packet>;
+}
+
+// Keeps packets to unknown addresses and never wakes the network
+impl Monitor for Vec<Packet> {
+    fn receive(&mut self, packet: Packet) {
+        self.push(packet);
+    }
+
+    fn wake(&mut self, _: NetState) -> Vec<Packet> {
+        Vec::new()
+    }
+}
+
+// What a machine did with its time slice
+enum Slice {
+    Busy,                  // Took input other than the idle input, produced output or used up the slice
+    Quiet,                 // Only waited, polled with the idle input or stayed halted
+    Stopped(MachineState), // Stopped for the host
+}
+
+#[derive(Debug)]
+pub(crate) struct Node {
+    pub(crate) vm: Machine,
+    pub(crate) link: Option<Link>,
+    pub(crate) partial: Vec<Word>, // Values of a packet not yet complete
+    pub(crate) last: Option<Word>, // Last value output
+}
+
+// Machines connected by links and run by a round-robin scheduler. A machine
+// without a link keeps its output in its output queue. The machines should
+// not have I/O devices attached, the scheduler moves values through the queues.
+#[derive(Debug, Default)]
+pub struct Network {
+    pub(crate) nodes: Vec<Node>,
+    idle: Option<Word>, // Read by a machine whose input queue is empty
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a machine, returns its node id
+    pub fn add(&mut self, vm: Machine) -> usize {
+        self.nodes.push(Node { vm, link: None, partial: Vec::new(), last: None });
+        self.nodes.len() - 1
+    }
+
+    // Adds a machine running a program with some initial input, e.g. a phase setting or address
+    pub fn spawn(&mut self, prog: &Program, inputs: &[Word]) -> usize {
+        let mut vm = Machine::new(prog);
+        vm.input_ext(inputs);
+        self.add(vm)
+    }
+
+    // Routes the output of a machine, replacing its previous link
+    pub fn link(&mut self, from: usize, link: Link) {
+        self.nodes[from].link = Some(link);
+    }
+
+    // Links the machines into a ring, each one piping into the next
+    pub fn ring(&mut self, ids: &[usize]) {
+        for (idx, &from) in ids.iter().enumerate() {
+            self.link(from, Link::Pipe(ids[(idx + 1) % ids.len()]));
+        }
+    }
+
+    // Sets the value a machine reads when its input queue is empty, -1 on day 23.
+    // None makes such machines wait for input.
+    pub fn idle_input(&mut self, value: Option<Word>) {
+        self.idle = value;
+    }
+
+    // Sends values to the input of a machine
+    pub fn send(&mut self, node: usize, values: &[Word]) {
+        self.nodes[node].vm.input_ext(values);
+    }
+
+    pub fn node(&self, node: usize) -> &Machine {
+        &self.nodes[node].vm
+    }
+
+    pub fn node_mut(&mut self, node: usize) -> &mut Machine {
+        &mut self.nodes[node].vm
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Last value a machine output, wherever it was routed
+    pub fn last_output(&self, node: usize) -> Option<Word> {
+        self.nodes[node].last
+    }
+
+    // Runs the machines until all halt, the network goes idle or deadlocks, or
+    // a machine stops otherwise. Packets to unknown addresses are dropped.
+    pub fn run(&mut self) -> Result<NetState, NodeFault> {
+        self.run_with(&mut Vec::new())
+    }
+
+    // Runs like `run`, handing packets to unknown addresses to a monitor that
+    // may also wake the network when it goes idle or deadlocks
+    pub fn run_with(&mut self, monitor: &mut impl Monitor) -> Result<NetState, NodeFault> {
+        loop {
+            let mut busy = false;
+            for node in 0..self.nodes.len() {
+                match self.step(node, monitor)? {
+                    Slice::Busy => busy = true,
+                    Slice::Quiet => {}
+                    Slice::Stopped(state) => return Ok(NetState::Stopped { node, state }),
+                }
+            }
+            if busy {
+                continue;
+            }
+
+            let state = if self.nodes.iter().all(|node| node.vm.state() == MachineState::Halted) {
+                return Ok(NetState::Halted);
+            } else if self.idle.is_some() {
+                NetState::Idle
+            } else {
+                NetState::Deadlocked
+            };
+            let packets = monitor.wake(state);
+            if packets.is_empty() {
+                return Ok(state);
+            }
+            packets.into_iter().for_each(|packet| self.deliver(packet, monitor));
+        }
+    }
+
+    // Gives a machine a time slice and routes its output
+    fn step(&mut self, node: usize, monitor: &mut impl Monitor) -> Result<Slice, NodeFault> {
+        let vm = &mut self.nodes[node].vm;
+        if vm.state() == MachineState::Halted {
+            return Ok(Slice::Quiet);
+        }
+        let polling = vm.state() == MachineState::AwaitingInput && vm.input_queue().is_empty();
+        if polling && let Some(value) = self.idle {
+            vm.input_ext(&[value]);
+        }
+        let (queued, outputs) = (vm.input_queue().len(), vm.output_len());
+
+        let state = vm.run_for(SLICE).map_err(|err| NodeFault { node, err })?;
+        let took_input = !polling && vm.input_queue().len() < queued;
+        let output = if self.nodes[node].link.is_some() {
+            self.nodes[node].vm.take_output()
+        } else {
+            self.nodes[node].vm.dump_output().iter().skip(outputs).copied().collect()
+        };
+        if let Some(&last) = output.last() {
+            self.nodes[node].last = Some(last);
+        }
+        let busy = took_input || !output.is_empty() || state == MachineState::Running;
+        self.route(node, output, monitor);
+
+        Ok(match state {
+            MachineState::Running
+            | MachineState::AwaitingInput
+            | MachineState::OutputFull
+            | MachineState::Halted => if busy { Slice::Busy } else { Slice::Quiet },
+            state => Slice::Stopped(state),
+        })
+    }
+
+    // Sends the output of a machine along its link
+    fn route(&mut self, node: usize, output: Vec<Word>, monitor: &mut impl Monitor) {
+        match self.nodes[node].link.clone() {
+            None => {}
+            Some(Link::Pipe(to)) => self.send(to, &output),
+            Some(Link::Broadcast(to)) => to.iter().for_each(|&to| self.send(to, &output)),
+            Some(Link::Packets) => {
+                let partial = &mut self.nodes[node].partial;
+                partial.extend(output);
+                let whole = partial.len() - partial.len() % 3;
+                let values: Vec<Word> = partial.drain(..whole).collect();
+                for chunk in values.chunks(3) {
+                    self.deliver(Packet { dest: chunk[0], x: chunk[1], y: chunk[2] }, monitor);
+                }
+            }
+        }
+    }
+
+    // Delivers a packet to its machine, or to the monitor if there is none at its address
+    fn deliver(&mut self, packet: Packet, monitor: &mut impl Monitor) {
+        match usize::try_from(packet.dest) {
+            Ok(dest) if dest < self.nodes.len() => self.send(dest, &[packet.x, packet.y]),
+            _ => monitor.receive(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assemble;
+
+    // Reads its address, then forwards each packet (x, y) to the next
+    // address as (x, y + 1), polling while its input is -1
+    const FORWARDER: &str = "
+            IN  [me]
+            ADD [me], #1, [next]
+    poll:   IN  [x]
+            EQ  [x], #-1, [t]
+            JNZ [t], #poll
+            IN  [y]
+            ADD [y], #1, [y]
+            OUT [next]
+            OUT [x]
+            OUT [y]
+            JZ  #0, #poll
+    me:     data 0
+    next:   data 0
+    x:      data 0
+    y:      data 0
+    t:      data 0
+    ";
+
+    // Passes the last packet it saw back to node 0 while y is below a limit
+    struct Nat {
+        seen: Vec<Packet>,
+        limit: Word,
+    }
+
+    impl Monitor for Nat {
+        fn receive(&mut self, packet: Packet) {
+            self.seen.push(packet);
+        }
+
+        fn wake(&mut self, state: NetState) -> Vec<Packet> {
+            assert_eq!(state, NetState::Idle);
+            match self.seen.last() {
+                Some(&Packet { x, y, .. }) if y < self.limit => vec![Packet { dest: 0, x, y }],
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn packets_and_monitor() {
+        let prog = assemble(FORWARDER).unwrap();
+        let mut net = Network::new();
+        for addr in 0..3 {
+            let node = net.spawn(&prog, &[addr]);
+            net.link(node, Link::Packets);
+        }
+        net.idle_input(Some(-1));
+        net.send(0, &[7, 0]);
+
+        let mut nat = Nat { seen: Vec::new(), limit: 9 };
+        assert_eq!(net.run_with(&mut nat), Ok(NetState::Idle));
+        let ys: Vec<Word> = nat.seen.iter().map(|packet| packet.y).collect();
+        assert_eq!(ys, vec![3, 6, 9]);
+        assert!(nat.seen.iter().all(|packet| packet.dest == 3 && packet.x == 7));
+        assert_eq!(net.last_output(2), Some(9));
+    }
+
+    #[test]
+    fn broadcast_then_deadlock() {
+        // Doubles every input
+        let double = vec![3,9,102,2,9,9,4,9,1105,1,0];
+        let mut net = Network::new();
+        let src = net.spawn(&double, &[1, 2]);
+        let left = net.spawn(&double, &[]);
+        let right = net.spawn(&double, &[]);
+        net.link(src, Link::Broadcast(vec![left, right]));
+        net.link(left, Link::Pipe(right));
+
+        assert_eq!(net.run(), Ok(NetState::Deadlocked));
+        assert_eq!(net.node(left).dump_output(), &Vec::<Word>::new());
+        assert_eq!(net.node(right).dump_output(), &vec![4, 8, 8, 16]);
+        assert_eq!(net.last_output(left), Some(8));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fmt, sync::Arc};
+use super::intcode::{Machine, Word};
+
+// Kind of memory access made by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Accesses a watchpoint fires on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Any,
+}
+
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::Any, _) | (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+// The breakpoint or watchpoint that stopped the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    // Stopped before executing the instruction at ip
+    Breakpoint { id: usize, ip: usize },
+    // Stopped after the instruction at ip accessed a watched cell
+    Watchpoint { id: usize, ip: usize, addr: usize, access: Access, value: Word },
+}
+
+impl Trap {
+    pub fn id(&self) -> usize {
+        match *self {
+            Trap::Breakpoint { id, .. } | Trap::Watchpoint { id, .. } => id,
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Trap::Breakpoint { id, ip } => write!(f, "breakpoint {id} at ip {ip}"),
+            Trap::Watchpoint { id, ip, addr, access, value } => {
+                write!(f, "watchpoint {id}: {access:?} of [{addr}] = {value} by ip {ip}")
+            }
+        }
+    }
+}
+
+pub type BreakCond = Arc<dyn Fn(&Machine) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+enum Breakpoint {
+    At { id: usize, addr: usize },
+    When { id: usize, cond: BreakCond },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Watchpoint {
+    id: usize,
+    addr: usize,
+    kind: WatchKind,
+}
+
+// Breakpoints and watchpoints set on a machine
+#[derive(Clone, Default)]
+pub(crate) struct Probes {
+    next_id: usize,
+    breaks: Vec<Breakpoint>,
+    watches: Vec<Watchpoint>,
+    pub(crate) skip: Option<usize>, // Address whose breakpoints are ignored once, when continuing
+    pub(crate) hit: Option<Trap>,   // Watchpoint fired by the current instruction
+}
+
+impl fmt::Debug for Probes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Probes")
+            .field("breaks", &self.breaks.len())
+            .field("watches", &self.watches)
+            .finish()
+    }
+}
+
+impl Probes {
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub(crate) fn break_at(&mut self, addr: usize) -> usize {
+        let id = self.next_id();
+        self.breaks.push(Breakpoint::At { id, addr });
+        id
+    }
+
+    pub(crate) fn break_if(&mut self, cond: BreakCond) -> usize {
+        let id = self.next_id();
+        self.breaks.push(Breakpoint::When { id, cond });
+        id
+    }
+
+    pub(crate) fn watch(&mut self, addr: usize, kind: WatchKind) -> usize {
+        let id = self.next_id();
+        self.watches.push(Watchpoint { id, addr, kind });
+        id
+    }
+
+    // Removes a breakpoint or watchpoint, returns false if there was none
+    pub(crate) fn remove(&mut self, id: usize) -> bool {
+        let count = self.breaks.len() + self.watches.len();
+        self.breaks.retain(|bp| !matches!(bp, Breakpoint::At { id: bid, .. } | Breakpoint::When { id: bid, .. } if *bid == id));
+        self.watches.retain(|wp| wp.id != id);
+        count != self.breaks.len() + self.watches.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.breaks.clear();
+        self.watches.clear();
+    }
+
+    pub(crate) fn has_breaks(&self) -> bool {
+        !self.breaks.is_empty()
+    }
+
+    pub(crate) fn has_watches(&self) -> bool {
+        !self.watches.is_empty()
+    }
+
+    // Addresses of the instruction breakpoints
+    pub(crate) fn break_addrs(&self) -> Vec<(usize, usize)> {
+        self.breaks
+            .iter()
+            .filter_map(|bp| match bp {
+                Breakpoint::At { id, addr } => Some((*id, *addr)),
+                Breakpoint::When { .. } => None,
+            })
+            .collect()
+    }
+
+    // Watched cells as (id, address, kind)
+    pub(crate) fn watch_list(&self) -> Vec<(usize, usize, WatchKind)> {
+        self.watches.iter().map(|wp| (wp.id, wp.addr, wp.kind)).collect()
+    }
+
+    // Checks the breakpoints before the instruction at ip executes
+    pub(crate) fn check_break(&self, vm: &Machine, ip: usize) -> Option<Trap> {
+        if self.skip == Some(ip) {
+            return None;
+        }
+
+        self.breaks.iter().find_map(|bp| match bp {
+            Breakpoint::At { id, addr } if *addr == ip => Some(Trap::Breakpoint { id: *id, ip }),
+            Breakpoint::When { id, cond } if cond(vm) => Some(Trap::Breakpoint { id: *id, ip }),
+            _ => None,
+        })
+    }
+
+    // Records the first watchpoint fired by an access of the current instruction
+    pub(crate) fn check_watch(&mut self, ip: usize, addr: usize, access: Access, value: Word) {
+        if self.hit.is_some() {
+            return;
+        }
+
+        self.hit = self
+            .watches
+            .iter()
+            .find(|wp| wp.addr == addr && wp.kind.matches(access))
+            .map(|wp| Trap::Watchpoint { id: wp.id, ip, addr, access, value });
+    }
+}
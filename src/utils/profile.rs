@@ -0,0 +1,228 @@
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+use super::disasm::{Inst, disassemble};
+use super::fuel::Counters;
+use super::intcode::{MachineState, OpInfo, Step, Word};
+use super::memory::PAGE;
+
+// A stretch of execution between two waits for input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub instructions: u64,
+    pub time: Duration, // Time spent running, not counting time the host held the machine
+}
+
+// A loop found by its backward jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotLoop {
+    pub start: usize,      // Target of the backward jump
+    pub jump: usize,       // Address of the backward jump
+    pub iterations: u64,   // Times the backward jump was taken
+    pub instructions: u64, // Instructions executed inside the loop body
+}
+
+// Execution profile of a machine: how often each address and opcode ran, the
+// backward jumps taken, and the time spent between input waits
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    hits: BTreeMap<usize, Box<[u64; PAGE]>>, // Instructions executed per address, in pages allocated when first hit
+    ops: Counters,                           // Instructions executed per opcode
+    back: BTreeMap<(usize, usize), u64>,     // Backward jumps taken per (jump, target)
+    spans: Vec<Span>,                        // Finished stretches between input waits
+    open: Span,                              // Stretch still running
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Instructions executed in total
+    pub fn instructions(&self) -> u64 {
+        self.ops.instructions()
+    }
+
+    // Instructions executed per opcode
+    pub fn counters(&self) -> &Counters {
+        &self.ops
+    }
+
+    // Times the instruction at an address was executed
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.hits.get(&(addr / PAGE)).map_or(0, |page| page[addr % PAGE])
+    }
+
+    // Instructions executed at the addresses of a range, inclusive
+    fn hits_between(&self, start: usize, end: usize) -> u64 {
+        self.hits
+            .range(start / PAGE..=end / PAGE)
+            .flat_map(|(&idx, page)| page.iter().enumerate().map(move |(offset, &count)| (idx * PAGE + offset, count)))
+            .filter(|&(addr, _)| (start..=end).contains(&addr))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    // Stretches between input waits, ending with the one still running if any
+    pub fn spans(&self) -> Vec<Span> {
+        let mut spans = self.spans.clone();
+        if self.open.instructions > 0 {
+            spans.push(self.open);
+        }
+        spans
+    }
+
+    // Addresses executed at least once with their counts, hottest first
+    pub fn hot_spots(&self) -> Vec<(usize, u64)> {
+        let mut spots: Vec<(usize, u64)> = self
+            .hits
+            .iter()
+            .flat_map(|(&idx, page)| page.iter().enumerate().map(move |(offset, &count)| (idx * PAGE + offset, count)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        spots
+    }
+
+    // Loops by backward jump, the most instructions first. A nested loop
+    // shows up on its own and inside the loop around it.
+    pub fn hot_loops(&self) -> Vec<HotLoop> {
+        let mut loops: Vec<HotLoop> = self
+            .back
+            .iter()
+            .map(|(&(jump, start), &iterations)| HotLoop {
+                start,
+                jump,
+                iterations,
+                instructions: self.hits_between(start, jump),
+            })
+            .collect();
+        loops.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    // Report of the `top` hottest loops and addresses, disassembled from the
+    // memory the program ran in, then the opcode mix and the stretches
+    // between input waits
+    pub fn report(&self, mem: &[Word], top: usize) -> String {
+        let total = self.instructions().max(1);
+        let pct = |count: u64| count as f64 * 100.0 / total as f64;
+        let spans = self.spans();
+        let time: Duration = spans.iter().map(|span| span.time).sum();
+        let labels = disassemble(mem).labels;
+        let mut out = String::new();
+
+        writeln!(out, "{} instructions in {} spans, {time:?}", self.instructions(), spans.len()).unwrap();
+
+        writeln!(out, "hot loops").unwrap();
+        for hot in self.hot_loops().iter().take(top) {
+            writeln!(
+                out,
+                "  {:04}..{:04}  {:>10} iterations  {:>12} instructions  {:5.1}%",
+                hot.start, hot.jump, hot.iterations, hot.instructions, pct(hot.instructions)
+            ).unwrap();
+        }
+
+        writeln!(out, "hot spots").unwrap();
+        for (addr, count) in self.hot_spots().into_iter().take(top) {
+            let text = Inst::decode(mem, addr).map_or("?".to_string(), |inst| inst.render(&labels));
+            let label = labels.get(&addr).map_or(String::new(), |label| format!("{label}:"));
+            writeln!(out, "  {addr:04}  {count:>12}  {:5.1}%  {label:<6} {text}", pct(count)).unwrap();
+        }
+
+        writeln!(out, "opcodes").unwrap();
+        for (opcode, count) in self.ops.iter() {
+            let mnemonic = OpInfo::lookup(opcode).map_or("?", |info| info.mnemonic);
+            writeln!(out, "  {mnemonic:<4} {count:>12}  {:5.1}%", pct(count)).unwrap();
+        }
+
+        writeln!(out, "spans").unwrap();
+        for (idx, span) in spans.iter().enumerate().take(top) {
+            writeln!(out, "  {idx:>4}  {:>12} instructions  {:?}", span.instructions, span.time).unwrap();
+        }
+        if spans.len() > top {
+            writeln!(out, "  ... {} more", spans.len() - top).unwrap();
+        }
+
+        out
+    }
+
+    // Counts an executed instruction
+    pub(crate) fn record(&mut self, step: &Step) {
+        self.hits.entry(step.ip / PAGE).or_insert_with(|| Box::new([0; PAGE]))[step.ip % PAGE] += 1;
+        self.ops.record(step.opcode);
+        self.open.instructions += 1;
+        if matches!(step.opcode, 5 | 6) && step.next_ip <= step.ip {
+            *self.back.entry((step.ip, step.next_ip)).or_default() += 1;
+        }
+    }
+
+    // Adds the time of a run, closing the stretch when the machine stopped
+    // to wait for input or for good
+    pub(crate) fn clock(&mut self, time: Duration, state: MachineState) {
+        self.open.time += time;
+        let waits = matches!(state, MachineState::AwaitingInput | MachineState::Halted | MachineState::Faulted(_));
+        if waits && self.open.instructions > 0 {
+            self.spans.push(std::mem::take(&mut self.open));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Machine, assemble};
+
+    // Counts each input down to zero, then outputs and waits for the next one
+    const COUNTDOWN: &str = "
+    start:  IN  [n]
+    loop:   ADD [n], #-1, [n]
+            JNZ [n], #loop
+            OUT #0
+            JZ  #0, #start
+    n:      data 0
+    ";
+
+    #[test]
+    fn loops_spots_and_spans() {
+        let prog = assemble(COUNTDOWN).unwrap();
+        let mut vm = Machine::new(&prog);
+        vm.start_profile();
+        vm.input_ext(&[3]);
+        assert_eq!(vm.run(), Ok(MachineState::AwaitingInput));
+        vm.input_ext(&[5]);
+        assert_eq!(vm.run(), Ok(MachineState::AwaitingInput));
+
+        let profile = vm.stop_profile().unwrap();
+        assert_eq!(profile.instructions(), 22);
+        assert_eq!(profile.counters().count(1), 8);
+        assert_eq!(profile.hits(2), 8);
+        assert_eq!(profile.hot_spots()[..2], [(2, 8), (6, 8)]);
+        assert_eq!(profile.hot_loops(), vec![
+            HotLoop { start: 0, jump: 11, iterations: 2, instructions: 22 },
+            HotLoop { start: 2, jump: 6, iterations: 6, instructions: 16 },
+        ]);
+        let counts: Vec<u64> = profile.spans().iter().map(|span| span.instructions).collect();
+        assert_eq!(counts, vec![9, 13]);
+
+        let report = profile.report(&vm.memory().prefix(), 3);
+        assert!(report.starts_with("22 instructions in 2 spans"));
+        assert!(report.contains("  0002..0006           6 iterations            16 instructions   72.7%"));
+        assert!(report.contains("ADD [14], #-1, [14]"));
+        assert!(vm.profile().is_none());
+    }
+
+    #[test]
+    fn far_jumps_stay_sparse() {
+        let mut vm = Machine::with_memory(crate::utils::Memory::paged(&[1105,1,5_000_000_000]));
+        vm.inject(5_000_000_000, 1105);
+        vm.inject(5_000_000_001, 1);
+        vm.inject(5_000_000_002, 3);
+        vm.inject(3, 99);
+        vm.start_profile();
+        assert_eq!(vm.run(), Ok(MachineState::Halted));
+
+        let profile = vm.stop_profile().unwrap();
+        assert_eq!(profile.hits.len(), 2);
+        assert_eq!(profile.hot_spots(), vec![(0, 1), (3, 1), (5_000_000_000, 1)]);
+        assert_eq!(profile.hot_loops(), vec![HotLoop { start: 3, jump: 5_000_000_000, iterations: 1, instructions: 2 }]);
+    }
+}
@@ -0,0 +1,515 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+use super::codec::{Reader, Truncated, put_list, put_varint, widen};
+use super::intcode::{MachineState, VmError, Word};
+use super::memory::Backend;
+
+// Text format, versioned by its first line and readable by eye:
+//
+//   intcode-snapshot 1
+//   ip 4
+//   rb 0
+//   state awaiting-input
+//   input 1 2
+//   output 3
+//   backend dense
+//   limit 1048576
+//   memory 1024
+//   0000 1002 4 3 4 33 0 0 0 0 0 0 0 0 0 0 0
+//
+// Memory rows hold 16 cells each, rows that are all zero are left out. A
+// faulted machine keeps its fault on the state line, e.g.
+// `state faulted invalid-opcode 4 42 42`. Without a limit the line reads
+// `limit none`. Snapshots from before the backend and limit were recorded
+// read as dense memory without a limit.
+//
+// Binary format: the magic `ICSN`, a version byte, then zigzag LEB128 varints.
+// Version 3 stores the backend, the limit (-1 for none), the memory length and
+// the non-zero cells as (gap, value) pairs. Version 2 lacked the backend and
+// limit, version 1 stored every cell, both are still read.
+
+const TEXT_HEADER: &str = "intcode-snapshot";
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u8 = 1;
+const BINARY_VERSION: u8 = 3;
+const ROW: usize = 16;
+
+// Problems reading a snapshot back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadMagic,
+    BadVersion(u8),
+    Truncated,
+    BadLine { line: usize, msg: String },
+    Io(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not an Intcode snapshot"),
+            SnapshotError::BadVersion(ver) => write!(f, "unsupported snapshot version {ver}"),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::BadLine { line, msg } => write!(f, "line {line}: {msg}"),
+            SnapshotError::Io(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<Truncated> for SnapshotError {
+    fn from(_: Truncated) -> Self {
+        SnapshotError::Truncated
+    }
+}
+
+// Complete state of a machine, see Machine::snapshot and Machine::restore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub ip: usize,
+    pub rb: Word,
+    pub state: MachineState,
+    pub backend: Backend,              // How the machine stored its memory
+    pub memory_limit: Option<usize>,   // Bound on the addresses the machine may use
+    pub memory_len: usize,             // One past the highest address touched
+    pub memory: BTreeMap<usize, Word>, // Non-zero cells by address
+    pub input: Vec<Word>,
+    pub output: Vec<Word>,
+}
+
+impl Snapshot {
+    // Exports to the text format
+    pub fn to_text(&self) -> String {
+        let join = |values: &[Word]| values.iter().map(|v| format!(" {v}")).collect::<String>();
+
+        let mut text = format!("{TEXT_HEADER} {VERSION}\n");
+        text.push_str(&format!("ip {}\nrb {}\n", self.ip, self.rb));
+        text.push_str(&format!("state {}\n", state_to_text(&self.state)));
+        text.push_str(&format!("input{}\noutput{}\n", join(&self.input), join(&self.output)));
+        text.push_str(&format!("backend {}\n", backend_to_text(self.backend)));
+        text.push_str(&format!("limit {}\n", self.memory_limit.map_or("none".to_string(), |limit| limit.to_string())));
+        text.push_str(&format!("memory {}\n", self.memory_len));
+        let mut rows: Vec<usize> = self.memory.keys().map(|addr| addr / ROW * ROW).collect();
+        rows.dedup();
+        for row in rows {
+            let cells: Vec<Word> = (row..(row + ROW).min(self.memory_len)).map(|addr| self.cell(addr)).collect();
+            text.push_str(&format!("{row:04}{}\n", join(&cells)));
+        }
+
+        text
+    }
+
+    // Reads the text format
+    pub fn from_text(text: &str) -> Result<Self, SnapshotError> {
+        let mut lines = text.lines().enumerate();
+        let bad = |line: usize, msg: String| SnapshotError::BadLine { line: line + 1, msg };
+
+        match lines.next().map(|(_, line)| line.split_whitespace().collect::<Vec<_>>()) {
+            Some(head) if head.len() == 2 && head[0] == TEXT_HEADER => {
+                if head[1] != VERSION.to_string() {
+                    return Err(SnapshotError::BadVersion(head[1].parse().unwrap_or(0)));
+                }
+            }
+            _ => return Err(SnapshotError::BadMagic),
+        }
+
+        let mut snap = Snapshot {
+            ip: 0,
+            rb: 0,
+            state: MachineState::Running,
+            backend: Backend::Dense,
+            memory_limit: None,
+            memory_len: 0,
+            memory: BTreeMap::new(),
+            input: Vec::new(),
+            output: Vec::new(),
+        };
+        for (idx, line) in lines {
+            let mut fields = line.split_whitespace();
+            let Some(key) = fields.next() else {
+                continue;
+            };
+            let values: Vec<&str> = fields.collect();
+            let nums = || values.iter().map(|v| v.parse::<Word>().map_err(|_| bad(idx, format!("invalid number '{v}'")))).collect::<Result<Vec<_>, _>>();
+            let one = || match nums()?.as_slice() {
+                [value] => Ok(*value),
+                _ => Err(bad(idx, format!("'{key}' takes a single value"))),
+            };
+
+            match key {
+                "ip" => snap.ip = usize::try_from(one()?).map_err(|_| bad(idx, "ip out of range".to_string()))?,
+                "rb" => snap.rb = one()?,
+                "state" => snap.state = state_from_text(&values).ok_or_else(|| bad(idx, format!("invalid state '{}'", values.join(" "))))?,
+                "input" => snap.input = nums()?,
+                "output" => snap.output = nums()?,
+                "backend" => {
+                    snap.backend = match values[..] {
+                        [name] => backend_from_text(name),
+                        _ => None,
+                    }
+                    .ok_or_else(|| bad(idx, format!("invalid backend '{}'", values.join(" "))))?
+                }
+                "limit" if values == ["none"] => snap.memory_limit = None,
+                "limit" => snap.memory_limit = Some(usize::try_from(one()?).map_err(|_| bad(idx, "limit out of range".to_string()))?),
+                "memory" => snap.memory_len = usize::try_from(one()?).map_err(|_| bad(idx, "memory length out of range".to_string()))?,
+                addr => {
+                    let addr: usize = addr.parse().map_err(|_| bad(idx, format!("unknown field '{addr}'")))?;
+                    let cells = nums()?;
+                    if addr.checked_add(cells.len()).is_none_or(|end| end > snap.memory_len) {
+                        return Err(bad(idx, "memory row past the end of memory".to_string()));
+                    }
+                    snap.set_cells(addr, &cells);
+                }
+            }
+        }
+
+        Ok(snap)
+    }
+
+    // Exports to the compact binary format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.push(BINARY_VERSION);
+        put_varint(&mut buf, self.ip as i128);
+        put_varint(&mut buf, widen(self.rb));
+        let (tag, fault) = state_to_fields(&self.state);
+        buf.push(tag);
+        put_list(&mut buf, fault.iter().map(|v| widen(*v)));
+        put_list(&mut buf, self.input.iter().map(|v| widen(*v)));
+        put_list(&mut buf, self.output.iter().map(|v| widen(*v)));
+        buf.push(self.backend as u8);
+        put_varint(&mut buf, self.memory_limit.map_or(-1, |limit| limit as i128));
+        put_varint(&mut buf, self.memory_len as i128);
+        let mut next = 0;
+        let pairs: Vec<i128> = self
+            .memory
+            .iter()
+            .flat_map(|(&addr, &value)| {
+                let gap = addr - next;
+                next = addr + 1;
+                [gap as i128, widen(value)]
+            })
+            .collect();
+        put_list(&mut buf, pairs.into_iter());
+        buf
+    }
+
+    // Reads the compact binary format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = bytes[4];
+        if !(1..=BINARY_VERSION).contains(&version) {
+            return Err(SnapshotError::BadVersion(version));
+        }
+
+        let bad = |msg: &str| SnapshotError::BadLine { line: 0, msg: msg.to_string() };
+        let word = |value: i128| Word::try_from(value).map_err(|_| bad("value out of range"));
+        let words = |list: Vec<i128>| list.into_iter().map(word).collect::<Result<Vec<_>, _>>();
+        let index = |value: i128, what: &str| usize::try_from(value).map_err(|_| bad(&format!("{what} out of range")));
+
+        let mut rd = Reader::new(bytes, 5);
+        let ip = index(rd.varint()?, "ip")?;
+        let rb = word(rd.varint()?)?;
+        let tag = rd.byte()?;
+        let fault = words(rd.list()?)?;
+        let state = state_from_fields(tag, &fault).ok_or_else(|| bad(&format!("invalid state tag {tag}")))?;
+        let input = words(rd.list()?)?;
+        let output = words(rd.list()?)?;
+        let mut snap = Snapshot {
+            ip,
+            rb,
+            state,
+            backend: Backend::Dense,
+            memory_limit: None,
+            memory_len: 0,
+            memory: BTreeMap::new(),
+            input,
+            output,
+        };
+        if version >= 3 {
+            snap.backend = match rd.byte()? {
+                0 => Backend::Dense,
+                1 => Backend::Paged,
+                tag => return Err(bad(&format!("invalid backend tag {tag}"))),
+            };
+            snap.memory_limit = match rd.varint()? {
+                -1 => None,
+                limit => Some(index(limit, "limit")?),
+            };
+        }
+        if version == 1 {
+            let cells = words(rd.list()?)?;
+            snap.memory_len = cells.len();
+            snap.set_cells(0, &cells);
+        } else {
+            snap.memory_len = index(rd.varint()?, "memory length")?;
+            let mut next: usize = 0;
+            for pair in rd.list()?.chunks(2) {
+                let [gap, value] = *pair else {
+                    return Err(bad("memory cell without a value"));
+                };
+                let addr = index(gap, "memory address")?
+                    .checked_add(next)
+                    .filter(|&addr| addr < snap.memory_len)
+                    .ok_or_else(|| bad("memory cell past the end of memory"))?;
+                snap.set_cells(addr, &[word(value)?]);
+                next = addr + 1;
+            }
+        }
+        if !rd.at_end() {
+            return Err(bad("trailing bytes"));
+        }
+
+        Ok(snap)
+    }
+
+    // Value of a memory cell
+    pub fn cell(&self, addr: usize) -> Word {
+        self.memory.get(&addr).copied().unwrap_or(0)
+    }
+
+    // Stores cells from an address on, keeping only the non-zero ones
+    fn set_cells(&mut self, addr: usize, cells: &[Word]) {
+        for (idx, &value) in cells.iter().enumerate() {
+            if value == 0 {
+                self.memory.remove(&(addr + idx));
+            } else {
+                self.memory.insert(addr + idx, value);
+            }
+        }
+    }
+
+    // Writes the text format to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        fs::write(path, self.to_text()).map_err(|err| SnapshotError::Io(err.to_string()))
+    }
+
+    // Writes the binary format to a file
+    pub fn save_binary(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        fs::write(path, self.to_bytes()).map_err(|err| SnapshotError::Io(err.to_string()))
+    }
+
+    // Reads a snapshot file in either format
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let bytes = fs::read(path).map_err(|err| SnapshotError::Io(err.to_string()))?;
+        if bytes.starts_with(MAGIC) {
+            Self::from_bytes(&bytes)
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| SnapshotError::BadMagic)?;
+            Self::from_text(&text)
+        }
+    }
+}
+
+// Names of the states as used in the text format, indexed by binary tag
+const STATES: [&str; 8] = [
+    "running", "awaiting-input", "output-ready", "paused", "halted", "faulted", "output-full", "out-of-fuel",
+];
+const FAULTS: [&str; 6] = [
+    "invalid-opcode", "invalid-mode", "negative-address", "write-in-immediate-mode", "memory-limit", "arithmetic-overflow",
+];
+
+// Binary tag and fault fields of a state. A machine stopped by a breakpoint
+// is saved as running, since breakpoints are not part of a snapshot.
+fn state_to_fields(state: &MachineState) -> (u8, Vec<Word>) {
+    match *state {
+        MachineState::Running | MachineState::Break(_) => (0, vec![]),
+        MachineState::AwaitingInput => (1, vec![]),
+        MachineState::OutputReady => (2, vec![]),
+        MachineState::Paused => (3, vec![]),
+        MachineState::Halted => (4, vec![]),
+        MachineState::OutputFull => (6, vec![]),
+        MachineState::OutOfFuel => (7, vec![]),
+        MachineState::Faulted(err) => {
+            let fields = match err {
+                VmError::InvalidOpcode { ip, word, opcode } => vec![0, ip as Word, word, opcode],
+                VmError::InvalidMode { ip, word, opcode, param, mode } => vec![1, ip as Word, word, opcode, param as Word, mode],
+                VmError::NegativeAddress { ip, word, opcode, param, mode, addr } => {
+                    vec![2, ip as Word, word, opcode, param as Word, mode, addr]
+                }
+                VmError::WriteInImmediateMode { ip, word, opcode, param } => vec![3, ip as Word, word, opcode, param as Word],
+                VmError::MemoryLimit { ip, word, opcode, param, addr } => {
+                    vec![4, ip as Word, word, opcode, param as Word, addr as Word]
+                }
+                VmError::ArithmeticOverflow { ip, word, opcode } => vec![5, ip as Word, word, opcode],
+            };
+            (5, fields)
+        }
+    }
+}
+
+fn state_from_fields(tag: u8, fields: &[Word]) -> Option<MachineState> {
+    let at = |value: Word| usize::try_from(value).ok();
+    Some(match tag {
+        0 => MachineState::Running,
+        1 => MachineState::AwaitingInput,
+        2 => MachineState::OutputReady,
+        3 => MachineState::Paused,
+        4 => MachineState::Halted,
+        6 => MachineState::OutputFull,
+        7 => MachineState::OutOfFuel,
+        5 => MachineState::Faulted(match *fields {
+            [0, ip, word, opcode] => VmError::InvalidOpcode { ip: at(ip)?, word, opcode },
+            [1, ip, word, opcode, param, mode] => {
+                VmError::InvalidMode { ip: at(ip)?, word, opcode, param: at(param)?, mode }
+            }
+            [2, ip, word, opcode, param, mode, addr] => {
+                VmError::NegativeAddress { ip: at(ip)?, word, opcode, param: at(param)?, mode, addr }
+            }
+            [3, ip, word, opcode, param] => VmError::WriteInImmediateMode { ip: at(ip)?, word, opcode, param: at(param)? },
+            [4, ip, word, opcode, param, addr] => {
+                // An address too large for a usize is saved as usize::MAX, which wraps to -1
+                let addr = if addr == -1 { usize::MAX } else { at(addr)? };
+                VmError::MemoryLimit { ip: at(ip)?, word, opcode, param: at(param)?, addr }
+            }
+            [5, ip, word, opcode] => VmError::ArithmeticOverflow { ip: at(ip)?, word, opcode },
+            _ => return None,
+        }),
+        _ => return None,
+    })
+}
+
+fn backend_to_text(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Dense => "dense",
+        Backend::Paged => "paged",
+    }
+}
+
+fn backend_from_text(name: &str) -> Option<Backend> {
+    [Backend::Dense, Backend::Paged].into_iter().find(|&backend| backend_to_text(backend) == name)
+}
+
+fn state_to_text(state: &MachineState) -> String {
+    let (tag, fields) = state_to_fields(state);
+    let mut text = STATES[tag as usize].to_string();
+    if let Some((kind, rest)) = fields.split_first() {
+        text.push(' ');
+        text.push_str(FAULTS[*kind as usize]);
+        rest.iter().for_each(|v| text.push_str(&format!(" {v}")));
+    }
+    text
+}
+
+fn state_from_text(values: &[&str]) -> Option<MachineState> {
+    let tag = STATES.iter().position(|name| Some(name) == values.first())?;
+    let mut fields = Vec::new();
+    if let Some(kind) = values.get(1) {
+        fields.push(FAULTS.iter().position(|name| name == kind)? as Word);
+        for value in &values[2..] {
+            fields.push(value.parse().ok()?);
+        }
+    }
+    state_from_fields(tag as u8, &fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{DEFAULT_MEMORY_LIMIT, Machine, Memory};
+    use crate::utils::memory::PAGE;
+
+    #[test]
+    fn snapshot_round_trips_and_resumes() {
+        let prog = vec![3,13,3,14,1,13,14,15,4,15,1105,1,0,0,0,0];
+        let mut vm = Machine::new(&prog);
+        vm.input_ext(&[20]);
+        vm.run().unwrap();
+        let snap = vm.snapshot();
+        assert_eq!(snap.state, MachineState::AwaitingInput);
+        assert_eq!(Snapshot::from_text(&snap.to_text()).unwrap(), snap);
+        assert_eq!(Snapshot::from_bytes(&snap.to_bytes()).unwrap(), snap);
+
+        let mut fork = Machine::from_snapshot(&Snapshot::from_text(&snap.to_text()).unwrap());
+        vm.input_ext(&[22]);
+        fork.input_ext(&[22]);
+        vm.run().unwrap();
+        fork.run().unwrap();
+        assert_eq!(fork.dump_output(), &vec![42]);
+        assert_eq!(fork.snapshot(), vm.snapshot());
+    }
+
+    #[test]
+    fn paged_memory_stays_sparse() {
+        let mut vm = Machine::with_memory(Memory::paged(&[1101,1,1,5_000_000_000,99]));
+        vm.run().unwrap();
+        let snap = vm.snapshot();
+        assert_eq!(snap.memory_len, 5_000_000_001);
+        assert_eq!(snap.memory.len(), 6);
+        assert!(snap.to_text().contains("backend paged\nlimit none\nmemory 5000000001\n0000 1101 1 1 5000000000 99 0 0"));
+        assert!(snap.to_text().ends_with(" 0 0\n5000000000 2\n"));
+        assert_eq!(Snapshot::from_text(&snap.to_text()).unwrap(), snap);
+        assert_eq!(Snapshot::from_bytes(&snap.to_bytes()).unwrap(), snap);
+
+        let fork = Machine::from_snapshot(&Snapshot::from_bytes(&snap.to_bytes()).unwrap());
+        assert_eq!(fork.memory().backend(), Backend::Paged);
+        assert_eq!(fork.read(5_000_000_000), 2);
+        assert_eq!(fork.memory().allocated(), vm.memory().allocated());
+
+        // Dense memory this large is restored paged
+        let huge = Snapshot::from_text("intcode-snapshot 1\nbackend dense\nmemory 5000000001\n5000000000 2\n").unwrap();
+        let fork = Machine::from_snapshot(&huge);
+        assert_eq!(fork.memory().backend(), Backend::Paged);
+        assert_eq!(fork.memory().allocated(), PAGE);
+    }
+
+    #[test]
+    fn backend_and_limit_are_kept() {
+        let vm = Machine::new(&vec![99]);
+        let snap = vm.snapshot();
+        assert!(snap.to_text().contains("backend dense\nlimit 1048576\n"));
+        let fork = Machine::from_snapshot(&Snapshot::from_bytes(&snap.to_bytes()).unwrap());
+        assert_eq!(fork.memory().backend(), Backend::Dense);
+        assert_eq!(fork.memory().limit(), Some(DEFAULT_MEMORY_LIMIT));
+        assert_eq!(Snapshot::from_text(&snap.to_text()).unwrap(), snap);
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let text = |body: &str| Snapshot::from_text(&format!("intcode-snapshot 1\n{body}"));
+        let bad_line = |result: Result<Snapshot, SnapshotError>| matches!(result, Err(SnapshotError::BadLine { .. }));
+        assert!(bad_line(text("ip -1\n")));
+        assert!(bad_line(text("memory -5\n")));
+        assert!(bad_line(text("memory 4\n0002 1 2 3\n")));
+        assert!(bad_line(text(&format!("memory 4\n{} 1 2\n", usize::MAX))));
+        assert!(bad_line(text("state faulted invalid-opcode -4 42 42\n")));
+        // A huge length costs nothing until cells are written
+        assert_eq!(text("memory 999999999999\n").unwrap().memory_len, 999_999_999_999);
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(BINARY_VERSION);
+        let mut ip = bytes.clone();
+        put_varint(&mut ip, -1);
+        assert!(bad_line(Snapshot::from_bytes(&ip)));
+        let mut rb = bytes.clone();
+        put_varint(&mut rb, 0);
+        put_varint(&mut rb, i128::MAX);
+        // Only out of range for 64-bit words, wide ones get as far as the missing state
+        let rb = Snapshot::from_bytes(&rb);
+        if Word::BITS < 128 {
+            assert!(bad_line(rb));
+        } else {
+            assert_eq!(rb, Err(SnapshotError::Truncated));
+        }
+
+        let snap = Machine::new(&vec![99]).snapshot();
+        let mut gap = snap.to_bytes();
+        gap.truncate(gap.len() - 5);
+        put_varint(&mut gap, 2);
+        put_list(&mut gap, [i128::from(u64::MAX), 7].into_iter());
+        assert!(bad_line(Snapshot::from_bytes(&gap)));
+        assert_eq!(Snapshot::from_bytes(&snap.to_bytes()[..8]), Err(SnapshotError::Truncated));
+    }
+
+    #[test]
+    fn faulted_state_is_kept() {
+        let mut vm = Machine::new(&vec![1101,1,1,5,42,0]);
+        vm.run().unwrap_err();
+        let snap = vm.snapshot();
+        assert!(snap.to_text().contains("state faulted invalid-opcode 4 42 42"));
+        assert_eq!(Snapshot::from_text(&snap.to_text()).unwrap().state, vm.state());
+        assert_eq!(Snapshot::from_bytes(&snap.to_bytes()).unwrap().state, vm.state());
+    }
+}
@@ -0,0 +1,557 @@
+use std::{collections::BTreeMap, fmt, ops::RangeInclusive};
+use super::fuel::Budget;
+use super::intcode::{Machine, MachineState, OpInfo, Program, Step, VmError, Word};
+
+// Instructions a single run may take unless told otherwise
+const FUEL: u64 = 1_000_000;
+
+// What the solver makes equal to the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    Cell(usize),   // A memory cell once the program stops
+    Output(usize), // The n-th output value
+}
+
+// Where a symbol's value goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Cell(usize),  // Injected into a memory cell before the run
+    Input(usize), // The n-th input value consumed
+}
+
+#[derive(Debug, Clone)]
+struct Symbol {
+    source: Source,
+    range: RangeInclusive<Word>, // Values the solver may choose
+}
+
+// A value computed from the symbols. Nodes live in an arena and only refer to
+// nodes pushed before them, so evaluating in order needs no recursion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Const(Word),
+    Sym(usize),
+    Add(usize, usize),
+    Mul(usize, usize),
+    Lt(usize, usize),
+    Eq(usize, usize),
+}
+
+// A decision of the run that depended on the symbols, with its outcome on
+// this path. Other values may take another path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Guard {
+    NonZero(usize, bool), // A jump condition, and whether it was non-zero
+    Equals(usize, Word),  // An address, jump target, relative base offset or instruction word
+}
+
+// Operand of an instruction during a concolic run
+#[derive(Debug, Clone, Copy)]
+enum Val {
+    Conc(Word),  // Same for every choice of symbols
+    Node(usize), // Computed from the symbols
+}
+
+// Sum of symbols times coefficients, plus a constant
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Linear {
+    pub terms: BTreeMap<usize, Word>, // Non-zero coefficients by symbol
+    pub constant: Word,
+}
+
+impl Linear {
+    fn constant(value: Word) -> Self {
+        Self { terms: BTreeMap::new(), constant: value }
+    }
+
+    fn symbol(sym: usize) -> Self {
+        Self { terms: BTreeMap::from([(sym, 1)]), constant: 0 }
+    }
+
+    // None if a coefficient overflows
+    fn add(&self, other: &Linear) -> Option<Linear> {
+        let mut sum = self.clone();
+        sum.constant = sum.constant.checked_add(other.constant)?;
+        for (&sym, &coeff) in &other.terms {
+            let total = sum.terms.get(&sym).copied().unwrap_or(0).checked_add(coeff)?;
+            if total == 0 {
+                sum.terms.remove(&sym);
+            } else {
+                sum.terms.insert(sym, total);
+            }
+        }
+        Some(sum)
+    }
+
+    fn scale(&self, factor: Word) -> Option<Linear> {
+        if factor == 0 {
+            return Some(Linear::constant(0));
+        }
+        let mut terms = BTreeMap::new();
+        for (&sym, &coeff) in &self.terms {
+            terms.insert(sym, coeff.checked_mul(factor)?);
+        }
+        Some(Linear { terms, constant: self.constant.checked_mul(factor)? })
+    }
+
+    fn as_constant(&self) -> Option<Word> {
+        self.terms.is_empty().then_some(self.constant)
+    }
+}
+
+// Symbols print as s0, s1... in the order they were declared
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = self
+            .terms
+            .iter()
+            .map(|(&sym, &coeff)| match coeff {
+                1 => format!("s{sym}"),
+                -1 => format!("-s{sym}"),
+                _ => format!("{coeff}*s{sym}"),
+            })
+            .collect();
+        if self.constant != 0 || parts.is_empty() {
+            parts.push(self.constant.to_string());
+        }
+        write!(f, "{}", parts.join(" + "))
+    }
+}
+
+// What one concolic run found out: the path it took and how the values it
+// left behind depend on the symbols along that path
+#[derive(Debug, Clone)]
+pub struct Exploration {
+    nodes: Vec<Node>,
+    lins: Vec<Option<Linear>>,     // Linear form of each node, None if not linear
+    guards: Vec<Guard>,
+    cells: BTreeMap<usize, usize>, // Node of each cell holding a symbolic value
+    outputs: Vec<Option<usize>>,   // Node of each output, None if concrete
+    vm: Machine,                   // The concrete machine where the run stopped
+}
+
+impl Exploration {
+    fn new(vm: Machine) -> Self {
+        Self { nodes: Vec::new(), lins: Vec::new(), guards: Vec::new(), cells: BTreeMap::new(), outputs: Vec::new(), vm }
+    }
+
+    // State the concrete run stopped in
+    pub fn state(&self) -> MachineState {
+        self.vm.state()
+    }
+
+    // Decisions along the path that depended on the symbols
+    pub fn decisions(&self) -> usize {
+        self.guards.len()
+    }
+
+    // The goal as a linear form of the symbols, valid for every choice that
+    // takes this path. None if the goal is not linear, or was not reached.
+    pub fn linear(&self, goal: Goal) -> Option<Linear> {
+        match self.goal_node(goal) {
+            Some(node) => self.lins[node].clone(),
+            None => self.concrete(goal).map(Linear::constant),
+        }
+    }
+
+    fn goal_node(&self, goal: Goal) -> Option<usize> {
+        match goal {
+            Goal::Cell(addr) => self.cells.get(&addr).copied(),
+            Goal::Output(idx) => self.outputs.get(idx).copied().flatten(),
+        }
+    }
+
+    // Value of the goal in the concrete run
+    fn concrete(&self, goal: Goal) -> Option<Word> {
+        goal_value(&self.vm, goal)
+    }
+
+    fn push(&mut self, node: Node) -> usize {
+        let lin = match node {
+            Node::Const(value) => Some(Linear::constant(value)),
+            Node::Sym(sym) => Some(Linear::symbol(sym)),
+            Node::Add(a, b) => match (&self.lins[a], &self.lins[b]) {
+                (Some(a), Some(b)) => a.add(b),
+                _ => None,
+            },
+            Node::Mul(a, b) => match (&self.lins[a], &self.lins[b]) {
+                (Some(a), Some(b)) => match (a.as_constant(), b.as_constant()) {
+                    (Some(k), _) => b.scale(k),
+                    (_, Some(k)) => a.scale(k),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Node::Lt(..) | Node::Eq(..) => None,
+        };
+        self.nodes.push(node);
+        self.lins.push(lin);
+        self.nodes.len() - 1
+    }
+
+    fn node(&mut self, val: Val) -> usize {
+        match val {
+            Val::Conc(value) => self.push(Node::Const(value)),
+            Val::Node(node) => node,
+        }
+    }
+
+    // Applies a binary operation, only building a node when a side is symbolic
+    fn binary(&mut self, a: Val, b: Val, node: fn(usize, usize) -> Node, op: fn(Word, Word) -> Word) -> Val {
+        match (a, b) {
+            (Val::Conc(a), Val::Conc(b)) => Val::Conc(op(a, b)),
+            _ => {
+                let (a, b) = (self.node(a), self.node(b));
+                Val::Node(self.push(node(a, b)))
+            }
+        }
+    }
+
+    // Follows an executed instruction, `sym` being the symbol its input stands for
+    fn record(&mut self, step: &Step, sym: Option<usize>) {
+        if let Some(&node) = self.cells.get(&step.ip) {
+            self.guards.push(Guard::Equals(node, step.word));
+        }
+
+        let dest = OpInfo::lookup(step.opcode).and_then(|info| info.dest);
+        let mut vals = [Val::Conc(0); 3];
+        for (idx, operand) in step.operands().iter().enumerate() {
+            let param = self.cells.get(&(step.ip + 1 + idx)).copied();
+            let is_dest = dest == Some(idx + 1);
+            vals[idx] = match (operand.mode, param) {
+                (1, Some(node)) => Val::Node(node),
+                (1, None) => Val::Conc(operand.value),
+                (_, param) => {
+                    // A symbolic address decides which cell is used, and whether it is valid
+                    if let Some(node) = param {
+                        self.guards.push(Guard::Equals(node, operand.raw));
+                    }
+                    match operand.addr.and_then(|addr| self.cells.get(&addr)) {
+                        Some(&node) if !is_dest => Val::Node(node),
+                        _ => Val::Conc(operand.value),
+                    }
+                }
+            };
+        }
+
+        let result = match step.opcode {
+            1 => Some(self.binary(vals[0], vals[1], Node::Add, Word::wrapping_add)),
+            2 => Some(self.binary(vals[0], vals[1], Node::Mul, Word::wrapping_mul)),
+            3 => Some(match sym {
+                Some(sym) => Val::Node(self.push(Node::Sym(sym))),
+                None => Val::Conc(step.input.unwrap_or(0)),
+            }),
+            4 => {
+                self.outputs.push(match vals[0] {
+                    Val::Node(node) => Some(node),
+                    Val::Conc(_) => None,
+                });
+                None
+            }
+            5 | 6 => {
+                let nonzero = step.operands[0].value != 0;
+                if let Val::Node(node) = vals[0] {
+                    self.guards.push(Guard::NonZero(node, nonzero));
+                }
+                if let Val::Node(node) = vals[1]
+                    && nonzero == (step.opcode == 5)
+                {
+                    self.guards.push(Guard::Equals(node, step.next_ip as Word));
+                }
+                None
+            }
+            7 => Some(self.binary(vals[0], vals[1], Node::Lt, |a, b| (a < b) as Word)),
+            8 => Some(self.binary(vals[0], vals[1], Node::Eq, |a, b| (a == b) as Word)),
+            9 => {
+                if let Val::Node(node) = vals[0] {
+                    self.guards.push(Guard::Equals(node, step.operands[0].value));
+                }
+                None
+            }
+            _ => None,
+        };
+
+        if let Some((addr, _)) = step.write {
+            match result {
+                Some(Val::Node(node)) => self.cells.insert(addr, node),
+                _ => self.cells.remove(&addr),
+            };
+        }
+    }
+
+    // Value of every node for a choice of symbols. Arithmetic wraps like the
+    // machine's default.
+    fn eval(&self, values: &[Word]) -> Vec<Word> {
+        let mut out: Vec<Word> = Vec::with_capacity(self.nodes.len());
+        for &node in &self.nodes {
+            let value = match node {
+                Node::Const(value) => value,
+                Node::Sym(sym) => values[sym],
+                Node::Add(a, b) => out[a].wrapping_add(out[b]),
+                Node::Mul(a, b) => out[a].wrapping_mul(out[b]),
+                Node::Lt(a, b) => (out[a] < out[b]) as Word,
+                Node::Eq(a, b) => (out[a] == out[b]) as Word,
+            };
+            out.push(value);
+        }
+        out
+    }
+
+    // Whether a choice of symbols meets the goal, if it is known to take this
+    // path. None when it may take another one.
+    fn check(&self, values: &[Word], goal: Goal, target: Word) -> Option<bool> {
+        let vals = self.eval(values);
+        let follows = self.guards.iter().all(|guard| match *guard {
+            Guard::NonZero(node, nonzero) => (vals[node] != 0) == nonzero,
+            Guard::Equals(node, expected) => vals[node] == expected,
+        });
+        if !follows {
+            return None;
+        }
+        let value = match self.goal_node(goal) {
+            Some(node) => vals[node],
+            None => self.concrete(goal)?,
+        };
+        Some(value == target)
+    }
+}
+
+fn goal_value(vm: &Machine, goal: Goal) -> Option<Word> {
+    match goal {
+        Goal::Cell(addr) => Some(vm.read(addr)),
+        Goal::Output(idx) => vm.dump_output().get(idx).copied(),
+    }
+}
+
+// Every choice from a list of ranges, the first range varying slowest
+fn product(ranges: Vec<RangeInclusive<Word>>) -> impl Iterator<Item = Vec<Word>> {
+    let first = ranges.iter().all(|range| !range.is_empty()).then(|| ranges.iter().map(|range| *range.start()).collect());
+    std::iter::successors(first, move |prev: &Vec<Word>| {
+        let mut next = prev.clone();
+        for idx in (0..next.len()).rev() {
+            if next[idx] < *ranges[idx].end() {
+                next[idx] += 1;
+                return Some(next);
+            }
+            next[idx] = *ranges[idx].start();
+        }
+        None
+    })
+}
+
+// Finds values for chosen memory cells and inputs that make a cell or an
+// output equal a target. A concolic run with the lowest values records how
+// everything depends on them; a linear goal is then solved directly, and
+// anything else falls back to a bounded search over the ranges. Every answer
+// either provably takes the recorded path or was confirmed by a real run.
+#[derive(Debug, Clone)]
+pub struct Solver {
+    prog: Program,
+    syms: Vec<Symbol>,
+    inputs: Vec<Word>, // Concrete input, symbolic inputs take their place
+    fuel: u64,         // Instructions per run, runs that need more are skipped
+}
+
+impl Solver {
+    pub fn new(prog: &Program) -> Self {
+        Self { prog: prog.clone(), syms: Vec::new(), inputs: Vec::new(), fuel: FUEL }
+    }
+
+    // Makes a memory cell a symbol, returns its index in a solution
+    pub fn cell(&mut self, addr: usize, range: RangeInclusive<Word>) -> usize {
+        self.declare(Source::Cell(addr), range)
+    }
+
+    // Makes the n-th input value a symbol, returns its index in a solution
+    pub fn input(&mut self, index: usize, range: RangeInclusive<Word>) -> usize {
+        self.declare(Source::Input(index), range)
+    }
+
+    // Concrete input for the positions that are not symbols
+    pub fn feed(&mut self, inputs: &[Word]) {
+        self.inputs.extend(inputs);
+    }
+
+    pub fn fuel(&mut self, limit: u64) {
+        self.fuel = limit;
+    }
+
+    fn declare(&mut self, source: Source, range: RangeInclusive<Word>) -> usize {
+        self.syms.push(Symbol { source, range });
+        self.syms.len() - 1
+    }
+
+    // A machine loaded with a choice of symbols
+    fn machine(&self, values: &[Word]) -> Machine {
+        let mut vm = Machine::new(&self.prog);
+        let mut inputs = self.inputs.clone();
+        for (sym, &value) in self.syms.iter().zip(values) {
+            match sym.source {
+                Source::Cell(addr) => vm.inject(addr, value),
+                Source::Input(idx) => {
+                    if idx >= inputs.len() {
+                        inputs.resize(idx + 1, 0);
+                    }
+                    inputs[idx] = value;
+                }
+            }
+        }
+        vm.input_ext(&inputs);
+        vm
+    }
+
+    // Runs the program with a choice of symbols, tracking how values depend on them
+    pub fn explore(&self, values: &[Word]) -> Result<Exploration, VmError> {
+        let mut path = Exploration::new(self.machine(values));
+        for (idx, sym) in self.syms.iter().enumerate() {
+            if let Source::Cell(addr) = sym.source {
+                let node = path.push(Node::Sym(idx));
+                path.cells.insert(addr, node);
+            }
+        }
+
+        let mut consumed = 0;
+        for _ in 0..self.fuel {
+            let Some(step) = path.vm.step()? else {
+                break;
+            };
+            let sym = step.input.and_then(|_| self.syms.iter().position(|sym| sym.source == Source::Input(consumed)));
+            consumed += step.input.is_some() as usize;
+            path.record(&step, sym);
+        }
+
+        Ok(path)
+    }
+
+    // Whether a choice of symbols meets the goal in a real run
+    fn verify(&self, values: &[Word], goal: Goal, target: Word) -> bool {
+        let mut vm = self.machine(values);
+        vm.refuel(Budget::Instructions(self.fuel));
+        matches!(vm.run(), Ok(MachineState::Halted | MachineState::AwaitingInput)) && goal_value(&vm, goal) == Some(target)
+    }
+
+    fn ranges(&self) -> Vec<RangeInclusive<Word>> {
+        self.syms.iter().map(|sym| sym.range.clone()).collect()
+    }
+
+    // Choices in range that solve a linear goal exactly, the last symbol with
+    // a coefficient being worked out from the others
+    fn linear_candidates(&self, lin: &Linear, target: Word) -> Box<dyn Iterator<Item = Vec<Word>> + '_> {
+        let Some((&solved, &coeff)) = lin.terms.iter().next_back() else {
+            return if lin.constant == target { Box::new(product(self.ranges())) } else { Box::new(std::iter::empty()) };
+        };
+        let mut ranges = self.ranges();
+        let range = ranges[solved].clone();
+        ranges[solved] = 0..=0;
+
+        let lin = lin.clone();
+        Box::new(product(ranges).filter_map(move |mut values| {
+            let mut rest = target.checked_sub(lin.constant)?;
+            for (&sym, &k) in lin.terms.iter().filter(|&(&sym, _)| sym != solved) {
+                rest = rest.checked_sub(k.checked_mul(values[sym])?)?;
+            }
+            let value = rest.checked_div(coeff)?;
+            (rest.checked_rem(coeff)? == 0 && range.contains(&value)).then(|| {
+                values[solved] = value;
+                values
+            })
+        }))
+    }
+
+    // Values for the symbols, in the order they were declared, that make the
+    // goal equal the target. None if no choice in range does.
+    pub fn solve(&self, goal: Goal, target: Word) -> Option<Vec<Word>> {
+        let lowest: Vec<Word> = self.syms.iter().map(|sym| *sym.range.start()).collect();
+        if self.syms.iter().any(|sym| sym.range.is_empty()) {
+            return None;
+        }
+        let path = self.explore(&lowest).ok().filter(|path| path.state() != MachineState::Running);
+        let meets = |values: &Vec<Word>| match path.as_ref().and_then(|path| path.check(values, goal, target)) {
+            Some(meets) => meets,
+            None => self.verify(values, goal, target),
+        };
+
+        if let Some(path) = &path
+            && let Some(lin) = path.linear(goal)
+        {
+            if let Some(values) = self.linear_candidates(&lin, target).find(&meets) {
+                return Some(values);
+            }
+            if path.guards.is_empty() {
+                // Every choice takes this path, so there is no other answer
+                return None;
+            }
+        }
+
+        product(self.ranges()).find(meets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assemble;
+
+    #[test]
+    fn linear_goal_through_symbolic_pointers() {
+        // Like day 2: the noun and verb are first used as addresses, then as values
+        let prog = vec![1,0,0,3, 1,1,2,3, 2,1,17,19, 1,19,2,0, 99, 300, 0, 0];
+        let mut solver = Solver::new(&prog);
+        let noun = solver.cell(1, 0..=99);
+        let verb = solver.cell(2, 0..=99);
+
+        let path = solver.explore(&[0, 0]).unwrap();
+        assert_eq!(path.state(), MachineState::Halted);
+        // Which cells the first instruction reads depends on the symbols
+        assert_eq!(path.decisions(), 2);
+        assert_eq!(path.linear(Goal::Cell(0)).unwrap().to_string(), "300*s0 + s1");
+        assert_eq!(path.linear(Goal::Cell(3)).unwrap().to_string(), "s0 + s1");
+
+        let values = solver.solve(Goal::Cell(0), 3634).unwrap();
+        assert_eq!((values[noun], values[verb]), (12, 34));
+        assert_eq!(solver.solve(Goal::Cell(0), 300 * 100), None);
+
+        // Negates a symbol and reads through it, which faults for a positive one
+        let prog = vec![1002,15,-1,5, 1001,0,0,13, 1001,15,0,14, 99,0,0,0];
+        let mut solver = Solver::new(&prog);
+        solver.cell(15, -5..=5);
+        assert_eq!(solver.solve(Goal::Cell(14), 3), None);
+        assert_eq!(solver.solve(Goal::Cell(14), -3), Some(vec![-3]));
+    }
+
+    // Outputs 3x+1 for an input x below 10, 5x otherwise, then whether x*x is 49
+    const BRANCHY: &str = "
+            IN  [x]
+            LT  [x], #10, [t]
+            JZ  [t], #big
+            MUL [x], #3, [y]
+            ADD [y], #1, [y]
+            JZ  #0, #out
+    big:    MUL [x], #5, [y]
+    out:    OUT [y]
+            MUL [x], [x], [y]
+            EQ  [y], #49, [y]
+            OUT [y]
+            HLT
+    x:      data 0
+    t:      data 0
+    y:      data 0
+    ";
+
+    #[test]
+    fn branches_and_non_linear_goals() {
+        let mut solver = Solver::new(&assemble(BRANCHY).unwrap());
+        solver.input(0, 0..=20);
+
+        let path = solver.explore(&[0]).unwrap();
+        assert_eq!(path.decisions(), 1);
+        assert_eq!(path.linear(Goal::Output(0)).unwrap().to_string(), "3*s0 + 1");
+        assert_eq!(path.linear(Goal::Output(1)), None);
+
+        // On the explored path, and on the other one only a real run can tell
+        assert_eq!(solver.solve(Goal::Output(0), 22), Some(vec![7]));
+        assert_eq!(solver.solve(Goal::Output(0), 50), Some(vec![10]));
+        assert_eq!(solver.solve(Goal::Output(0), 46), None);
+        assert_eq!(solver.solve(Goal::Output(1), 1), Some(vec![7]));
+    }
+}
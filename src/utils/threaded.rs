@@ -0,0 +1,361 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, mpsc::{self, Receiver, Sender}};
+use rayon::prelude::*;
+use super::device::{InputPort, OutputPort, Ports};
+use super::intcode::{Machine, MachineState, Word};
+use super::network::{Link, Monitor, NetState, Network, NodeFault, Packet, SLICE};
+
+// A message to a machine's input
+enum Msg {
+    Value(Word),
+    Stop, // The run is over, stop reading
+}
+
+// What the threads of a run share, guarded by one lock
+#[derive(Default)]
+struct Activity {
+    active: usize,           // Machines neither parked on input nor finished
+    queued: Vec<usize>,      // Values sent to each machine and not yet read
+    done: Vec<bool>,         // Machines whose thread has finished
+    halted: usize,           // Machines that halted
+    partial: Vec<Vec<Word>>, // Values of each machine's packet not yet complete
+    last: Vec<Option<Word>>, // Last value each machine output
+    unrouted: Vec<Packet>,   // Packets to addresses with no machine, for the monitor
+    // How the run ended, set early when a machine faults or stops
+    outcome: Option<Result<NetState, NodeFault>>,
+}
+
+impl Activity {
+    // Every machine is finished or parked with nothing to read
+    fn quiescent(&self) -> bool {
+        self.active == 0 && self.done.iter().zip(&self.queued).all(|(&done, &queued)| done || queued == 0)
+    }
+}
+
+struct Shared {
+    activity: Mutex<Activity>,
+    changed: Condvar,
+    inboxes: Vec<Sender<Msg>>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap()
+    }
+
+    // Sends a value to a machine, counting it until it is read
+    fn post(&self, act: &mut Activity, to: usize, value: Word) {
+        act.queued[to] += 1;
+        let _ = self.inboxes[to].send(Msg::Value(value));
+    }
+
+    // Delivers a packet to its machine, or keeps it for the monitor
+    fn deliver(&self, act: &mut Activity, packet: Packet) {
+        match usize::try_from(packet.dest) {
+            Ok(dest) if dest < self.inboxes.len() => {
+                self.post(act, dest, packet.x);
+                self.post(act, dest, packet.y);
+            }
+            _ => act.unrouted.push(packet),
+        }
+    }
+}
+
+// Input of a machine on its own thread, parks the thread while there is nothing to read
+struct Inbox {
+    node: usize,
+    rx: Arc<Mutex<Receiver<Msg>>>,
+    shared: Arc<Shared>,
+}
+
+impl InputPort for Inbox {
+    fn read(&mut self) -> Option<Word> {
+        let rx = self.rx.lock().unwrap();
+        let (msg, parked) = match rx.try_recv() {
+            Ok(msg) => (msg, false),
+            Err(_) => {
+                let mut act = self.shared.lock();
+                act.active -= 1;
+                self.shared.changed.notify_all();
+                drop(act);
+                (rx.recv().unwrap_or(Msg::Stop), true)
+            }
+        };
+
+        let mut act = self.shared.lock();
+        act.active += parked as usize;
+        match msg {
+            Msg::Value(value) => {
+                act.queued[self.node] -= 1;
+                Some(value)
+            }
+            Msg::Stop => None,
+        }
+    }
+}
+
+// Output of a machine on its own thread, routed along its link as it is written
+struct Outlet {
+    node: usize,
+    link: Link,
+    shared: Arc<Shared>,
+}
+
+impl OutputPort for Outlet {
+    fn write(&mut self, value: Word) {
+        let mut act = self.shared.lock();
+        act.last[self.node] = Some(value);
+        match &self.link {
+            Link::Pipe(to) => self.shared.post(&mut act, *to, value),
+            Link::Broadcast(to) => to.iter().for_each(|&to| self.shared.post(&mut act, to, value)),
+            Link::Packets => {
+                act.partial[self.node].push(value);
+                if let [dest, x, y] = act.partial[self.node][..] {
+                    act.partial[self.node].clear();
+                    self.shared.deliver(&mut act, Packet { dest, x, y });
+                    self.shared.changed.notify_all();
+                }
+            }
+        }
+    }
+}
+
+impl Network {
+    // Runs every machine on its own thread until all halt, the network
+    // deadlocks, or a machine stops otherwise. Reading input with nothing sent
+    // parks the thread, there is no idle input. Packets to unknown addresses
+    // are dropped. Devices the caller attached are set aside for the run and
+    // attached again after it.
+    pub fn run_threaded(&mut self) -> Result<NetState, NodeFault> {
+        self.run_threaded_with(&mut Vec::new())
+    }
+
+    // Runs like `run_threaded`, handing packets to unknown addresses to a
+    // monitor that may also wake the network when it deadlocks
+    pub fn run_threaded_with(&mut self, monitor: &mut impl Monitor) -> Result<NetState, NodeFault> {
+        let count = self.nodes.len();
+        let (inboxes, receivers): (Vec<_>, Vec<_>) = (0..count).map(|_| mpsc::channel()).unzip();
+        let receivers: Vec<_> = receivers.into_iter().map(|rx| Arc::new(Mutex::new(rx))).collect();
+        let done: Vec<bool> = self.nodes.iter().map(|node| node.vm.state() == MachineState::Halted).collect();
+        let activity = Activity {
+            active: done.iter().filter(|&&done| !done).count(),
+            queued: vec![0; count],
+            halted: done.iter().filter(|&&done| done).count(),
+            done,
+            partial: self.nodes.iter().map(|node| node.partial.clone()).collect(),
+            last: self.nodes.iter().map(|node| node.last).collect(),
+            ..Activity::default()
+        };
+        let shared = Arc::new(Shared { activity: Mutex::new(activity), changed: Condvar::new(), inboxes });
+        let outputs: Vec<usize> = self.nodes.iter().map(|node| node.vm.output_len()).collect();
+        let devices: Vec<Ports> = self.nodes.iter_mut().map(|node| node.vm.swap_devices(Ports::default())).collect();
+
+        let outcome = std::thread::scope(|scope| {
+            for (id, node) in self.nodes.iter_mut().enumerate() {
+                if node.vm.state() == MachineState::Halted {
+                    continue;
+                }
+                node.vm.attach_input(Inbox { node: id, rx: receivers[id].clone(), shared: shared.clone() });
+                if let Some(link) = node.link.clone() {
+                    node.vm.attach_output(Outlet { node: id, link, shared: shared.clone() });
+                }
+                let shared = shared.clone();
+                scope.spawn(move || run_node(id, &mut node.vm, &shared));
+            }
+
+            let outcome = supervise(&shared, monitor);
+            for inbox in &shared.inboxes {
+                let _ = inbox.send(Msg::Stop);
+            }
+            outcome
+        });
+
+        // Values nobody read stay in the input queues, as with the scheduler
+        let act = shared.lock();
+        for ((id, node), ports) in self.nodes.iter_mut().enumerate().zip(devices) {
+            node.vm.swap_devices(ports);
+            let rx = receivers[id].lock().unwrap();
+            let unread: Vec<Word> = rx.try_iter().filter_map(|msg| match msg {
+                Msg::Value(value) => Some(value),
+                Msg::Stop => None,
+            }).collect();
+            node.vm.input_ext(&unread);
+            node.partial = act.partial[id].clone();
+            node.last = act.last[id];
+            // Output of a machine without a link stays in its queue
+            if node.vm.output_len() > outputs[id] {
+                node.last = node.vm.dump_output().back().copied();
+            }
+        }
+        outcome
+    }
+}
+
+// Runs a machine on its thread until it halts, is told to stop or stops the run
+fn run_node(id: usize, vm: &mut Machine, shared: &Shared) {
+    let outcome = loop {
+        match vm.run_for(SLICE) {
+            Ok(MachineState::Running) if shared.lock().outcome.is_none() => continue,
+            // Running out of input means the run is over
+            Ok(MachineState::Running | MachineState::AwaitingInput | MachineState::Halted) => break None,
+            Ok(state) => break Some(Ok(NetState::Stopped { node: id, state })),
+            Err(err) => break Some(Err(NodeFault { node: id, err })),
+        }
+    };
+
+    let mut act = shared.lock();
+    act.active -= 1;
+    act.done[id] = true;
+    act.halted += (vm.state() == MachineState::Halted) as usize;
+    if act.outcome.is_none() {
+        act.outcome = outcome;
+    }
+    shared.changed.notify_all();
+}
+
+// Waits for the machines to halt, quiesce or stop the run, feeding the monitor on the way
+fn supervise(shared: &Shared, monitor: &mut impl Monitor) -> Result<NetState, NodeFault> {
+    let mut act = shared.lock();
+    loop {
+        act = shared.changed.wait_while(act, |act| {
+            act.outcome.is_none() && act.unrouted.is_empty() && !act.quiescent()
+        }).unwrap();
+        act.unrouted.drain(..).for_each(|packet| monitor.receive(packet));
+
+        if let Some(outcome) = act.outcome {
+            return outcome;
+        }
+        if !act.quiescent() {
+            continue;
+        }
+        if act.halted == act.done.len() {
+            act.outcome = Some(Ok(NetState::Halted));
+            return Ok(NetState::Halted);
+        }
+
+        let packets = monitor.wake(NetState::Deadlocked);
+        if packets.is_empty() {
+            act.outcome = Some(Ok(NetState::Deadlocked));
+            return Ok(NetState::Deadlocked);
+        }
+        packets.into_iter().for_each(|packet| shared.deliver(&mut act, packet));
+    }
+}
+
+// Runs independent cases in parallel on the rayon pool, results in case order
+pub fn batch_map<C: Send, R: Send>(cases: Vec<C>, run: impl Fn(C) -> R + Sync + Send) -> Vec<R> {
+    cases.into_par_iter().map(run).collect()
+}
+
+// Runs independent cases in parallel, returning the result of the first case,
+// in case order, for which `run` returns one
+pub fn batch_find<C: Send, R: Send>(cases: Vec<C>, run: impl Fn(C) -> Option<R> + Sync + Send) -> Option<R> {
+    cases.into_par_iter().find_map_first(run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Program, assemble};
+    use std::collections::VecDeque;
+    use itertools::Itertools;
+
+    // Amplifier feedback loop example of day 7
+    const AMP: [Word; 29] = [3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+
+    fn amp_loop(prog: &Program, phases: &[Word]) -> Word {
+        let mut net = Network::new();
+        let amps: Vec<usize> = phases.iter().map(|&phase| net.spawn(prog, &[phase])).collect();
+        net.ring(&amps);
+        net.send(amps[0], &[0]);
+        assert_eq!(net.run_threaded(), Ok(NetState::Halted));
+        net.last_output(amps[4]).unwrap()
+    }
+
+    #[test]
+    fn threaded_feedback_loop_and_batch() {
+        let prog = AMP.to_vec();
+        assert_eq!(amp_loop(&prog, &[9, 8, 7, 6, 5]), 139629729);
+
+        let cases: Vec<Vec<Word>> = (5..=9).permutations(5).collect();
+        let outputs = batch_map(cases.clone(), |phases| amp_loop(&prog, &phases));
+        assert_eq!(outputs.iter().max(), Some(&139629729));
+        let best = batch_find(cases, |phases| (amp_loop(&prog, &phases) == 139629729).then_some(phases));
+        assert_eq!(best, Some(vec![9, 8, 7, 6, 5]));
+    }
+
+    // Forwards packets (x, y) to the next address as (x, y + 1)
+    const FORWARDER: &str = "
+            IN  [me]
+            ADD [me], #1, [next]
+    loop:   IN  [x]
+            IN  [y]
+            ADD [y], #1, [y]
+            OUT [next]
+            OUT [x]
+            OUT [y]
+            JZ  #0, #loop
+    me:     data 0
+    next:   data 0
+    x:      data 0
+    y:      data 0
+    ";
+
+    // Sends packets that left the network back to node 0 until y reaches 9
+    #[derive(Default)]
+    struct Loopback(Vec<Word>);
+
+    impl Monitor for Loopback {
+        fn receive(&mut self, packet: Packet) {
+            self.0.push(packet.y);
+        }
+
+        fn wake(&mut self, state: NetState) -> Vec<Packet> {
+            assert_eq!(state, NetState::Deadlocked);
+            match self.0.last() {
+                Some(&y) if y < 9 => vec![Packet { dest: 0, x: 7, y }],
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn threaded_quiescence_and_monitor() {
+        let prog = assemble(FORWARDER).unwrap();
+        let mut net = Network::new();
+        for addr in 0..3 {
+            let node = net.spawn(&prog, &[addr]);
+            net.link(node, Link::Packets);
+        }
+        net.send(0, &[7, 0]);
+
+        let mut seen = Loopback::default();
+        assert_eq!(net.run_threaded_with(&mut seen), Ok(NetState::Deadlocked));
+        assert_eq!(seen.0, vec![3, 6, 9]);
+        assert!((0..3).all(|node| net.node(node).state() == MachineState::AwaitingInput));
+
+        // The machines carry on cooperatively where the threads left them
+        net.send(1, &[7, 0]);
+        let mut seen = Loopback::default();
+        assert_eq!(net.run_with(&mut seen), Ok(NetState::Deadlocked));
+        assert_eq!(seen.0, vec![2, 5, 8, 11]);
+    }
+
+    #[test]
+    fn unlinked_output_and_caller_devices() {
+        // Doubles every input
+        let double = vec![3,9,102,2,9,9,4,9,1105,1,0];
+        let mut net = Network::new();
+        let node = net.spawn(&double, &[1, 2, 3]);
+        let mut vm = Machine::new(&double);
+        vm.attach_input(VecDeque::from([5]));
+        let other = net.add(vm);
+
+        assert_eq!(net.run_threaded(), Ok(NetState::Deadlocked));
+        assert_eq!(net.last_output(node), Some(6));
+        assert_eq!(net.node(node).dump_output(), &vec![2, 4, 6]);
+
+        // The device was set aside for the threads and is back afterwards
+        assert_eq!(net.last_output(other), None);
+        assert!(net.node_mut(other).detach_input().is_some());
+    }
+}
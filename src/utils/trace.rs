@@ -0,0 +1,416 @@
+use std::fmt;
+use super::codec::{Reader, Truncated, put_varint, widen};
+use super::intcode::{Machine, OpInfo, Operand, Program, Step, VmError, Word};
+
+// Text format, one executed instruction per line:
+//
+//   <ip> <word> <mnemonic> ops=<mode>:<raw>:<addr>:<value>;... w=<addr>:<value> rb=<rb> next=<ip> in=<v> out=<v>
+//
+// Absent values are written as `-`. Lines starting with `#` are comments.
+// Operands the instruction did not use, like the target of a jump not taken,
+// are written as `<mode>:<raw>`.
+//
+// Binary format: the magic `ICTR`, a version byte, then the step count and
+// every step as zigzag LEB128 varints. Version 1 did not record the operands
+// a jump left unused and is no longer read.
+
+const MAGIC: &[u8; 4] = b"ICTR";
+const VERSION: u8 = 2;
+
+// Problems reading a trace back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceError {
+    BadMagic,
+    BadVersion(u8),
+    Truncated,
+    BadLine { line: usize, msg: String },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::BadMagic => write!(f, "not an Intcode trace"),
+            TraceError::BadVersion(ver) => write!(f, "unsupported trace version {ver}"),
+            TraceError::Truncated => write!(f, "trace is truncated"),
+            TraceError::BadLine { line, msg } => write!(f, "line {line}: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<Truncated> for TraceError {
+    fn from(_: Truncated) -> Self {
+        TraceError::Truncated
+    }
+}
+
+// Where a replay first differs from the recorded trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub index: usize,           // Index of the step in the trace
+    pub expected: Step,         // Recorded step
+    pub found: Option<Step>,    // Step executed by the replay, None if nothing ran
+    pub fault: Option<VmError>, // Fault raised by the replay, if any
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "diverged at step {} (ip {})", self.index, self.expected.ip)?;
+        writeln!(f, "  expected: {}", fmt_step(&self.expected))?;
+        match (&self.found, &self.fault) {
+            (_, Some(err)) => write!(f, "  found:    fault, {err}"),
+            (Some(step), None) => write!(f, "  found:    {}", fmt_step(step)),
+            (None, None) => write!(f, "  found:    nothing, machine stopped"),
+        }
+    }
+}
+
+// Recorded execution of a machine
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub steps: Vec<Step>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    // Inputs consumed, in order
+    pub fn inputs(&self) -> Vec<Word> {
+        self.steps.iter().filter_map(|step| step.input).collect()
+    }
+
+    // Outputs produced, in order
+    pub fn outputs(&self) -> Vec<Word> {
+        self.steps.iter().filter_map(|step| step.output).collect()
+    }
+
+    // Exports to the line-oriented text format
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for step in &self.steps {
+            text.push_str(&fmt_step(step));
+            text.push('\n');
+        }
+        text
+    }
+
+    // Reads the line-oriented text format
+    pub fn from_text(text: &str) -> Result<Self, TraceError> {
+        let mut trace = Self::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = parse_step(line).map_err(|msg| TraceError::BadLine { line: idx + 1, msg })?;
+            trace.push(step);
+        }
+        Ok(trace)
+    }
+
+    // Exports to the compact binary format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        put_varint(&mut buf, self.steps.len() as i128);
+
+        for step in &self.steps {
+            put_varint(&mut buf, step.ip as i128);
+            put_varint(&mut buf, widen(step.word));
+            put_varint(&mut buf, step.read as i128);
+            for (idx, op) in step.operands[..step.arity].iter().enumerate() {
+                put_varint(&mut buf, widen(op.raw));
+                if idx < step.read {
+                    put_varint(&mut buf, op.addr.map_or(-1, |addr| addr as i128));
+                    put_varint(&mut buf, widen(op.value));
+                }
+            }
+            let flags = step.write.is_some() as u8 | (step.input.is_some() as u8) << 1 | (step.output.is_some() as u8) << 2;
+            buf.push(flags);
+            if let Some((addr, value)) = step.write {
+                put_varint(&mut buf, addr as i128);
+                put_varint(&mut buf, widen(value));
+            }
+            for value in step.input.iter().chain(step.output.iter()) {
+                put_varint(&mut buf, widen(*value));
+            }
+            put_varint(&mut buf, step.next_ip as i128);
+            put_varint(&mut buf, widen(step.rb));
+        }
+
+        buf
+    }
+
+    // Reads the compact binary format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TraceError> {
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err(TraceError::BadMagic);
+        }
+        if bytes[4] != VERSION {
+            return Err(TraceError::BadVersion(bytes[4]));
+        }
+
+        let bad = |msg: &str| TraceError::BadLine { line: 0, msg: msg.to_string() };
+        let word = |value: i128| Word::try_from(value).map_err(|_| bad("value out of range"));
+        let index = |value: i128, what: &str| usize::try_from(value).map_err(|_| bad(&format!("{what} out of range")));
+
+        let mut rd = Reader::new(bytes, 5);
+        let count = index(rd.varint()?, "step count")?;
+        let mut trace = Self::new();
+
+        for _ in 0..count {
+            let ip = index(rd.varint()?, "ip")?;
+            let mut step = decoded(ip, word(rd.varint()?)?);
+            step.read = index(rd.varint()?, "operand count")?;
+            if step.read > step.arity {
+                return Err(bad(&format!("expected at most {} operands, found {}", step.arity, step.read)));
+            }
+            for idx in 0..step.arity {
+                step.operands[idx].raw = word(rd.varint()?)?;
+                if idx < step.read {
+                    step.operands[idx].addr = match rd.varint()? {
+                        -1 => None,
+                        addr => Some(index(addr, "operand address")?),
+                    };
+                    step.operands[idx].value = word(rd.varint()?)?;
+                }
+            }
+            let flags = rd.byte()?;
+            if flags & !7 != 0 {
+                return Err(bad(&format!("invalid flags {flags}")));
+            }
+            if flags & 1 != 0 {
+                step.write = Some((index(rd.varint()?, "write address")?, word(rd.varint()?)?));
+            }
+            if flags & 2 != 0 {
+                step.input = Some(word(rd.varint()?)?);
+            }
+            if flags & 4 != 0 {
+                step.output = Some(word(rd.varint()?)?);
+            }
+            step.next_ip = index(rd.varint()?, "next ip")?;
+            step.rb = word(rd.varint()?)?;
+            trace.push(step);
+        }
+        if !rd.at_end() {
+            return Err(bad("trailing bytes"));
+        }
+
+        Ok(trace)
+    }
+
+    // Re-runs a program with the recorded inputs and checks it against the trace
+    pub fn replay(&self, prog: &Program) -> Result<(), Box<Divergence>> {
+        self.replay_on(&mut Machine::new(prog))
+    }
+
+    // Replays the trace on a machine prepared by the caller, e.g. with injected memory
+    pub fn replay_on(&self, vm: &mut Machine) -> Result<(), Box<Divergence>> {
+        vm.input_ext(&self.inputs());
+
+        for (index, expected) in self.steps.iter().enumerate() {
+            let diverged = |found, fault| Box::new(Divergence { index, expected: *expected, found, fault });
+            match vm.step() {
+                Ok(Some(step)) if step == *expected => {}
+                Ok(found) => return Err(diverged(found, None)),
+                Err(err) => return Err(diverged(None, Some(err))),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Step with the fields implied by the instruction word filled in
+fn decoded(ip: usize, word: Word) -> Step {
+    let mut modes = [0; 3];
+    let mut code = word / 100;
+    for mode in modes.iter_mut() {
+        *mode = code % 10;
+        code /= 10;
+    }
+    let arity = OpInfo::lookup(word % 100).map_or(0, |info| info.arity);
+    let mut operands = [Operand::default(); 3];
+    for (op, mode) in operands.iter_mut().zip(modes).take(arity) {
+        op.mode = mode;
+    }
+
+    Step { ip, word, opcode: word % 100, modes, arity, operands, ..Step::default() }
+}
+
+fn opt<T: fmt::Display>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+// Formats a step as a line of the text format
+pub fn fmt_step(step: &Step) -> String {
+    let mnemonic = OpInfo::lookup(step.opcode).map_or("???", |info| info.mnemonic);
+    let ops: Vec<String> = step.operands[..step.arity]
+        .iter()
+        .enumerate()
+        .map(|(idx, op)| {
+            if idx < step.read {
+                format!("{}:{}:{}:{}", op.mode, op.raw, opt(op.addr), op.value)
+            } else {
+                format!("{}:{}", op.mode, op.raw)
+            }
+        })
+        .collect();
+    let write = opt(step.write.map(|(addr, value)| format!("{addr}:{value}")));
+
+    format!(
+        "{} {} {} ops={} w={} rb={} next={} in={} out={}",
+        step.ip,
+        step.word,
+        mnemonic,
+        if ops.is_empty() { "-".to_string() } else { ops.join(";") },
+        write,
+        step.rb,
+        step.next_ip,
+        opt(step.input),
+        opt(step.output),
+    )
+}
+
+// Parses a line of the text format
+fn parse_step(line: &str) -> Result<Step, String> {
+    fn num<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+        text.parse().map_err(|_| format!("invalid number '{text}'"))
+    }
+    fn opt_num<T: std::str::FromStr>(text: &str) -> Result<Option<T>, String> {
+        if text == "-" { Ok(None) } else { num(text).map(Some) }
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 9 {
+        return Err(format!("expected 9 fields, found {}", fields.len()));
+    }
+    let mut step = decoded(num(fields[0])?, num(fields[1])?);
+
+    for field in &fields[3..] {
+        let (key, value) = field.split_once('=').ok_or_else(|| format!("expected key=value, found '{field}'"))?;
+        match key {
+            "ops" if value == "-" => {}
+            "ops" => {
+                let ops: Vec<&str> = value.split(';').collect();
+                if ops.len() != step.arity {
+                    return Err(format!("expected {} operands, found {}", step.arity, ops.len()));
+                }
+                for (idx, op) in ops.iter().enumerate() {
+                    let parts: Vec<&str> = op.split(':').collect();
+                    let operand = &mut step.operands[idx];
+                    match parts[..] {
+                        [mode, raw, addr, value] if step.read == idx => {
+                            *operand = Operand { mode: num(mode)?, raw: num(raw)?, addr: opt_num(addr)?, value: num(value)? };
+                            step.read += 1;
+                        }
+                        [mode, raw] => *operand = Operand { mode: num(mode)?, raw: num(raw)?, ..Operand::default() },
+                        _ => return Err(format!("invalid operand '{op}'")),
+                    }
+                }
+            }
+            "w" if value == "-" => {}
+            "w" => {
+                let (addr, val) = value.split_once(':').ok_or_else(|| format!("invalid write '{value}'"))?;
+                step.write = Some((num(addr)?, num(val)?));
+            }
+            "rb" => step.rb = num(value)?,
+            "next" => step.next_ip = num(value)?,
+            "in" => step.input = opt_num(value)?,
+            "out" => step.output = opt_num(value)?,
+            _ => return Err(format!("unknown field '{key}'")),
+        }
+    }
+
+    Ok(step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traced(prog: &Program, inputs: &[Word]) -> Trace {
+        let mut vm = Machine::new(prog);
+        vm.start_trace();
+        vm.input_ext(inputs);
+        vm.run().unwrap();
+        vm.stop_trace().unwrap()
+    }
+
+    #[test]
+    fn text_and_binary_round_trip() {
+        let prog = vec![109,3,3,1,20201,-3,1,0,204,0,99];
+        let trace = traced(&prog, &[5]);
+        assert_eq!(trace.len(), 5);
+        assert_eq!(trace.outputs(), vec![109 + 5]);
+        assert_eq!(Trace::from_text(&trace.to_text()).unwrap(), trace);
+        assert_eq!(Trace::from_bytes(&trace.to_bytes()).unwrap(), trace);
+    }
+
+    #[test]
+    fn round_trip_with_jump_not_taken() {
+        let prog = vec![1105,0,7,104,5,99,0,99];
+        let trace = traced(&prog, &[]);
+        assert_eq!(trace.steps[0].read, 1);
+        assert!(trace.to_text().starts_with("0 1105 JNZ ops=1:0:-:0;1:7 "));
+        assert_eq!(Trace::from_text(&trace.to_text()).unwrap(), trace);
+        assert_eq!(Trace::from_bytes(&trace.to_bytes()).unwrap(), trace);
+    }
+
+    #[test]
+    fn malformed_binary_is_rejected() {
+        let bytes = traced(&vec![104,5,99], &[]).to_bytes();
+        let bad_line = |result: Result<Trace, TraceError>| matches!(result, Err(TraceError::BadLine { .. }));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(bad_line(Trace::from_bytes(&trailing)));
+
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        let mut count = header.clone();
+        put_varint(&mut count, -1);
+        assert!(bad_line(Trace::from_bytes(&count)));
+        let mut ip = header.clone();
+        put_varint(&mut ip, 1);
+        put_varint(&mut ip, -4);
+        assert!(bad_line(Trace::from_bytes(&ip)));
+        let mut read = header.clone();
+        for value in [1, 0, 104, 2] {
+            put_varint(&mut read, value);
+        }
+        assert!(bad_line(Trace::from_bytes(&read)));
+
+        assert_eq!(Trace::from_bytes(&bytes[..bytes.len() - 1]), Err(TraceError::Truncated));
+        assert_eq!(Trace::from_bytes(b"ICTR\x01"), Err(TraceError::BadVersion(1)));
+    }
+
+    #[test]
+    fn replay_points_at_divergence() {
+        let prog = vec![3,9,1001,9,2,9,4,9,99,0];
+        let trace = traced(&prog, &[40]);
+        assert_eq!(trace.replay(&prog), Ok(()));
+
+        let mut typo = prog.clone();
+        typo[4] = 3;
+        let div = trace.replay(&typo).unwrap_err();
+        assert_eq!(div.index, 1);
+        assert_eq!(div.expected.ip, 2);
+        assert_eq!(div.found.unwrap().write, Some((9, 43)));
+    }
+}